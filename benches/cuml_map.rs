@@ -0,0 +1,243 @@
+#[macro_use]
+extern crate criterion;
+extern crate cuml_map;
+
+use criterion::{black_box, Criterion};
+use cuml_map::*;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The system allocator, counting the bytes currently allocated, so that we
+/// can compare the memory taken by each structure.
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const BENCH_1: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/bench_1");
+
+/// Read a workload file, as generated by `src/genbench.py`: a line holding
+/// the key capacity, followed by one `key value` update per line.
+fn load_updates(fname: &str) -> (usize, Vec<i64>, Vec<i64>) {
+    use std::fs::File;
+    use std::io::prelude::*;
+    use std::io::BufReader;
+
+    let fp = File::open(fname).expect("Could not open file");
+    let mut reader = BufReader::new(fp);
+    let mut line = String::new();
+
+    let _ = reader.read_line(&mut line);
+    let cap = line.trim().parse::<usize>().expect("Bad parse");
+    let mut keys = Vec::new();
+    let mut vals = Vec::new();
+
+    for line in reader.lines() {
+        let kv: Vec<i64> = line
+            .expect("Could not read line")
+            .split(' ')
+            .map(|x| x.trim().parse::<i64>().expect("Bad parse"))
+            .collect();
+        keys.push(kv[0]);
+        vals.push(kv[1]);
+    }
+
+    (cap, keys, vals)
+}
+
+macro_rules! bench_build {
+    ($group:expr, $name:expr, $k:ty, $keys:expr, $vals:expr, $init:expr) => {
+        $group.bench_function($name, |b| {
+            b.iter(|| {
+                let mut cm = $init;
+                for i in 0..$keys.len() {
+                    cm.insert($keys[i] as $k, $vals[i]);
+                }
+                cm
+            })
+        });
+    };
+}
+
+fn build_1(c: &mut Criterion) {
+    let (_cap, keys, vals) = load_updates(BENCH_1);
+    let mut group = c.benchmark_group("build_1");
+    bench_build!(group, "ftf", usize, keys, vals, FenwickTree::with_capacity(1000));
+    bench_build!(group, "fte", i64, keys, vals, ExtensibleFenwickTree::new());
+    bench_build!(group, "ftc", i64, keys, vals, ExtensibleFenwickTree::with_capacity(1000));
+    bench_build!(group, "rbt", i64, keys, vals, CumlTree::new());
+    bench_build!(group, "sft", u64, keys, vals, SparseFenwickTree::new());
+    group.finish();
+}
+
+macro_rules! bench_getc {
+    ($group:expr, $name:expr, $k:ty, $keys:expr, $vals:expr, $init:expr) => {
+        let mut cm = $init;
+        for i in 0..$keys.len() {
+            cm.insert($keys[i] as $k, $vals[i]);
+        }
+        let mx = *$keys.iter().max().unwrap();
+        $group.bench_function($name, |b| {
+            b.iter(|| {
+                for i in 0..mx {
+                    black_box(cm.get_cuml(black_box(i) as $k));
+                }
+            })
+        });
+    };
+}
+
+fn getc_1(c: &mut Criterion) {
+    let (_cap, keys, vals) = load_updates(BENCH_1);
+    let mut group = c.benchmark_group("getc_1");
+    bench_getc!(group, "ftf", usize, keys, vals, FenwickTree::with_capacity(1000));
+    bench_getc!(group, "fte", i64, keys, vals, ExtensibleFenwickTree::new());
+    bench_getc!(group, "ftc", i64, keys, vals, ExtensibleFenwickTree::with_capacity(1000));
+    bench_getc!(group, "rbt", i64, keys, vals, CumlTree::new());
+    bench_getc!(group, "sft", u64, keys, vals, SparseFenwickTree::new());
+    group.finish();
+}
+
+macro_rules! bench_degen {
+    ($group:expr, $name:expr, $k:ty, $init:expr) => {
+        $group.bench_function($name, |b| {
+            b.iter(|| {
+                let mut cm = $init;
+                for i in 1..1000i64 {
+                    cm.insert(i as $k, i);
+                }
+                cm
+            })
+        });
+    };
+}
+
+fn build_degen(c: &mut Criterion) {
+    let mut group = c.benchmark_group("build_degen");
+    bench_degen!(group, "ftf", usize, FenwickTree::with_capacity(1000));
+    bench_degen!(group, "fte", i64, ExtensibleFenwickTree::new());
+    bench_degen!(group, "ftc", i64, ExtensibleFenwickTree::with_capacity(1000));
+    bench_degen!(group, "rbt", i64, CumlTree::new());
+    bench_degen!(group, "sft", u64, SparseFenwickTree::new());
+    group.finish();
+}
+
+macro_rules! bench_getc_degen {
+    ($group:expr, $name:expr, $k:ty, $init:expr) => {
+        let mut cm = $init;
+        for i in 1..1000i64 {
+            cm.insert(i as $k, i);
+        }
+        $group.bench_function($name, |b| {
+            b.iter(|| {
+                for i in 0..1000 {
+                    black_box(cm.get_cuml(black_box(i) as $k));
+                }
+            })
+        });
+    };
+}
+
+fn getc_degen(c: &mut Criterion) {
+    let mut group = c.benchmark_group("getc_degen");
+    bench_getc_degen!(group, "ftf", usize, FenwickTree::with_capacity(1000));
+    bench_getc_degen!(group, "fte", i64, ExtensibleFenwickTree::new());
+    bench_getc_degen!(group, "ftc", i64, ExtensibleFenwickTree::with_capacity(1000));
+    bench_getc_degen!(group, "rbt", i64, CumlTree::new());
+    bench_getc_degen!(group, "sft", u64, SparseFenwickTree::new());
+    group.finish();
+}
+
+/// A workload of `n` updates at keys spread over the whole `u64` key space.
+fn sparse_updates(n: u64) -> (Vec<u64>, Vec<i64>) {
+    let keys = (0..n).map(|i| i.wrapping_mul(0x9e37_79b9_7f4a_7c15)).collect();
+    let vals = (0..n).map(|i| (i % 100) as i64).collect();
+    (keys, vals)
+}
+
+fn build_sparse(c: &mut Criterion) {
+    let (keys, vals) = sparse_updates(10_000);
+    let mut group = c.benchmark_group("build_sparse");
+    bench_build!(group, "sft", u64, keys, vals, SparseFenwickTree::new());
+    bench_build!(group, "rbt", u64, keys, vals, CumlTree::new());
+    group.finish();
+}
+
+macro_rules! bench_getc_sparse {
+    ($group:expr, $name:expr, $keys:expr, $vals:expr, $init:expr) => {
+        let mut cm = $init;
+        for i in 0..$keys.len() {
+            cm.insert($keys[i], $vals[i]);
+        }
+        $group.bench_function($name, |b| {
+            b.iter(|| {
+                for k in $keys.iter() {
+                    black_box(cm.get_cuml(black_box(k ^ 1)));
+                }
+            })
+        });
+    };
+}
+
+fn getc_sparse(c: &mut Criterion) {
+    let (keys, vals) = sparse_updates(10_000);
+    let mut group = c.benchmark_group("getc_sparse");
+    bench_getc_sparse!(group, "sft", keys, vals, SparseFenwickTree::new());
+    bench_getc_sparse!(group, "rbt", keys, vals, CumlTree::new());
+    group.finish();
+}
+
+macro_rules! report_memory {
+    ($name:expr, $keys:expr, $vals:expr, $init:expr) => {
+        let before = ALLOCATED.load(Ordering::Relaxed);
+        let mut cm = $init;
+        for i in 0..$keys.len() {
+            cm.insert($keys[i], $vals[i]);
+        }
+        let bytes = ALLOCATED.load(Ordering::Relaxed) - before;
+        println!(
+            "memory_sparse/{}: {} bytes for {} keys ({:.1} bytes per key)",
+            $name,
+            bytes,
+            $keys.len(),
+            bytes as f64 / $keys.len() as f64
+        );
+        black_box(cm);
+    };
+}
+
+/// Report the heap memory taken by each structure for the sparse workload,
+/// which criterion does not measure itself.
+fn memory_sparse(_c: &mut Criterion) {
+    for &n in &[10_000, 1_000_000] {
+        let (keys, vals) = sparse_updates(n);
+        report_memory!("sft", keys, vals, SparseFenwickTree::new());
+        report_memory!("rbt", keys, vals, CumlTree::new());
+    }
+}
+
+criterion_group!(
+    benches,
+    build_1,
+    getc_1,
+    build_degen,
+    getc_degen,
+    build_sparse,
+    getc_sparse,
+    memory_sparse
+);
+criterion_main!(benches);
@@ -0,0 +1,371 @@
+extern crate num_traits;
+use num_traits::{CheckedAdd, CheckedSub, Float};
+use std::cmp::Ordering;
+use std::num::{Saturating, Wrapping};
+
+/*****************************************************************************
+ * Algebraic structures for the values stored in a mapping
+ *****************************************************************************/
+
+/// A type with an associative operation, `combine`, and an identity element
+/// for that operation.
+pub trait Monoid: Clone + PartialEq {
+    /// The identity element, which leaves any value unchanged when combined
+    /// with it. For numbers this is zero.
+    fn identity() -> Self;
+
+    /// Combine two values. For numbers this is addition.
+    fn combine(&self, other: &Self) -> Self;
+}
+
+/// A `Monoid` whose operation is commutative and can be undone. This is
+/// what the values of every `CumlMap` implementation in this crate must be.
+///
+/// For types such as unsigned integers, which have no inverses of their own,
+/// `combine_inverse` need only be defined where the result would be
+/// representable; the mappings in this crate never call it otherwise, so long
+/// as every value inserted is itself representable.
+pub trait Group: Monoid {
+    /// Combine `self` with the inverse of `other`, so that
+    /// `a.combine(&b).combine_inverse(&b) == a`. For numbers this is
+    /// subtraction.
+    fn combine_inverse(&self, other: &Self) -> Self;
+}
+
+/// Combine `n` copies of `v`, by repeated doubling. None of the partial
+/// sums computed is larger than the result.
+pub(crate) fn times<V: Monoid>(v: &V, n: usize) -> V {
+    let mut n = n;
+    let mut acc = V::identity();
+    let mut pow = v.clone();
+    while n > 0 {
+        if n & 1 == 1 {
+            acc = acc.combine(&pow);
+        }
+        n >>= 1;
+        if n > 0 {
+            pow = pow.combine(&pow);
+        }
+    }
+    acc
+}
+
+macro_rules! impl_group_for_num {
+    ($($t:ty),*) => {
+        $(
+            impl Monoid for $t {
+                fn identity() -> Self {
+                    0 as $t
+                }
+
+                fn combine(&self, other: &Self) -> Self {
+                    *self + *other
+                }
+            }
+
+            impl Group for $t {
+                fn combine_inverse(&self, other: &Self) -> Self {
+                    *self - *other
+                }
+            }
+        )*
+    };
+}
+
+impl_group_for_num!(i8, i16, i32, i64, i128, isize);
+impl_group_for_num!(u8, u16, u32, u64, u128, usize);
+impl_group_for_num!(f32, f64);
+
+macro_rules! impl_group_for_wrapping {
+    ($($t:ty),*) => {
+        $(
+            impl Monoid for Wrapping<$t> {
+                fn identity() -> Self {
+                    Wrapping(0)
+                }
+
+                fn combine(&self, other: &Self) -> Self {
+                    *self + *other
+                }
+            }
+
+            impl Group for Wrapping<$t> {
+                fn combine_inverse(&self, other: &Self) -> Self {
+                    *self - *other
+                }
+            }
+        )*
+    };
+}
+
+impl_group_for_wrapping!(i8, i16, i32, i64, i128, isize);
+impl_group_for_wrapping!(u8, u16, u32, u64, u128, usize);
+
+/// Saturating values stop at the bounds of their type rather than
+/// overflowing. This makes them only an approximate group: once a sum has
+/// saturated, subtracting a value from it no longer undoes adding that value.
+/// See `FenwickTree` for how this affects its queries.
+macro_rules! impl_group_for_saturating {
+    ($($t:ty),*) => {
+        $(
+            impl Monoid for Saturating<$t> {
+                fn identity() -> Self {
+                    Saturating(0)
+                }
+
+                fn combine(&self, other: &Self) -> Self {
+                    *self + *other
+                }
+            }
+
+            impl Group for Saturating<$t> {
+                fn combine_inverse(&self, other: &Self) -> Self {
+                    *self - *other
+                }
+            }
+        )*
+    };
+}
+
+impl_group_for_saturating!(i8, i16, i32, i64, i128, isize);
+impl_group_for_saturating!(u8, u16, u32, u64, u128, usize);
+
+/// An integer whose arithmetic panics on overflow in every build profile,
+/// rather than only in debug builds, as plain integers do. This is the
+/// counterpart of `std::num::Wrapping` and `std::num::Saturating` for values
+/// which should never overflow; to handle overflow as an error instead, use
+/// the methods of `CheckedCumlMap` on plain integers.
+///
+/// # Examples
+/// ```
+/// use cuml_map::{Checked, CumlMap, FenwickTree};
+///
+/// let mut ft: FenwickTree<Checked<u8>> = FenwickTree::with_capacity(4);
+/// ft.insert(0, Checked(200));
+/// ft.insert(3, Checked(50));
+/// assert_eq!(ft.get_cuml(3), Checked(250));
+/// ```
+///
+/// ```should_panic
+/// use cuml_map::{Checked, CumlMap, FenwickTree};
+///
+/// let mut ft: FenwickTree<Checked<u8>> = FenwickTree::with_capacity(4);
+/// ft.insert(1, Checked(200));
+/// ft.insert(2, Checked(100));
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Checked<T>(pub T);
+
+impl<T> Monoid for Checked<T>
+where
+    T: Monoid + CheckedAdd + CheckedSub,
+{
+    fn identity() -> Self {
+        Checked(T::identity())
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        Checked(self.0.checked_add(&other.0).expect("Overflow in Checked addition"))
+    }
+}
+
+impl<T> Group for Checked<T>
+where
+    T: Monoid + CheckedAdd + CheckedSub,
+{
+    fn combine_inverse(&self, other: &Self) -> Self {
+        Checked(self.0.checked_sub(&other.0).expect("Overflow in Checked subtraction"))
+    }
+}
+
+macro_rules! impl_group_for_tuple {
+    ($($t:ident $i:tt),*) => {
+        impl<$($t: Monoid),*> Monoid for ($($t,)*) {
+            fn identity() -> Self {
+                ($($t::identity(),)*)
+            }
+
+            fn combine(&self, other: &Self) -> Self {
+                ($(self.$i.combine(&other.$i),)*)
+            }
+        }
+
+        impl<$($t: Group),*> Group for ($($t,)*) {
+            fn combine_inverse(&self, other: &Self) -> Self {
+                ($(self.$i.combine_inverse(&other.$i),)*)
+            }
+        }
+    };
+}
+
+impl_group_for_tuple!(A 0, B 1);
+impl_group_for_tuple!(A 0, B 1, C 2);
+impl_group_for_tuple!(A 0, B 1, C 2, D 3);
+
+/// Vectors are combined element by element, with the shorter vector treated
+/// as though it were padded with identity elements, so that vectors of any
+/// length (including the empty identity vector) may be mixed freely. Trailing
+/// identity elements are trimmed from the result, so that equal sums compare
+/// equal whatever their original lengths.
+impl<T: Monoid> Monoid for Vec<T> {
+    fn identity() -> Self {
+        Vec::new()
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        zip_padded(self, other, T::combine)
+    }
+}
+
+impl<T: Group> Group for Vec<T> {
+    fn combine_inverse(&self, other: &Self) -> Self {
+        zip_padded(self, other, T::combine_inverse)
+    }
+}
+
+fn zip_padded<T, F>(a: &[T], b: &[T], f: F) -> Vec<T>
+where
+    T: Monoid,
+    F: Fn(&T, &T) -> T,
+{
+    let id = T::identity();
+    let mut out: Vec<T> = (0..a.len().max(b.len()))
+        .map(|i| f(a.get(i).unwrap_or(&id), b.get(i).unwrap_or(&id)))
+        .collect();
+    while out.last() == Some(&id) {
+        out.pop();
+    }
+    out
+}
+
+/// A value which has been computed with checked arithmetic, and is `None` if
+/// any step overflowed. Running the generic queries over these, rather than
+/// over the plain values, reports overflow rather than panicking or wrapping.
+#[derive(Clone, PartialEq)]
+pub(crate) struct CheckedSum<V>(pub(crate) Option<V>);
+
+impl<V: Clone> CheckedSum<V> {
+    pub(crate) fn of(v: &V) -> Self {
+        CheckedSum(Some(v.clone()))
+    }
+}
+
+impl<V> Monoid for CheckedSum<V>
+where
+    V: Monoid + CheckedAdd + CheckedSub,
+{
+    fn identity() -> Self {
+        CheckedSum(Some(V::identity()))
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        match (&self.0, &other.0) {
+            (Some(a), Some(b)) => CheckedSum(a.checked_add(b)),
+            _ => CheckedSum(None),
+        }
+    }
+}
+
+impl<V> Group for CheckedSum<V>
+where
+    V: Monoid + CheckedAdd + CheckedSub,
+{
+    fn combine_inverse(&self, other: &Self) -> Self {
+        match (&self.0, &other.0) {
+            (Some(a), Some(b)) => CheckedSum(a.checked_sub(b)),
+            _ => CheckedSum(None),
+        }
+    }
+}
+
+/*****************************************************************************
+ * Compensated floating-point summation
+ *****************************************************************************/
+
+/// A floating-point value which carries a running compensation term, so that
+/// sums of many values (as computed by a `FenwickTree` storing
+/// `Compensated<f64>` rather than `f64`) suffer far less rounding drift.
+/// This uses Neumaier's variant of Kahan summation.
+///
+/// Values compare equal and are ordered according to `value`.
+///
+/// # Examples
+/// ```
+/// use cuml_map::{Compensated, CumlMap, FenwickTree};
+///
+/// let mut plain: FenwickTree<f64> = FenwickTree::with_capacity(3);
+/// let mut comp: FenwickTree<Compensated<f64>> = FenwickTree::with_capacity(3);
+/// for (k, &v) in [1e16, 1.0, -1e16].iter().enumerate() {
+///     plain.insert(k, v);
+///     comp.insert(k, Compensated::new(v));
+/// }
+/// assert_eq!(plain.get_cuml(2), 0.0);
+/// assert_eq!(comp.get_cuml(2).value(), 1.0);
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Compensated<F> {
+    sum: F,
+    err: F,
+}
+
+impl<F: Float> Compensated<F> {
+    /// Wrap a single floating-point value.
+    pub fn new(x: F) -> Self {
+        Compensated {
+            sum: x,
+            err: F::zero(),
+        }
+    }
+
+    /// Get the best estimate of the value represented.
+    pub fn value(&self) -> F {
+        self.sum + self.err
+    }
+
+    fn add(&self, sum: F, err: F) -> Self {
+        let t = self.sum + sum;
+        let lost = if self.sum.abs() >= sum.abs() {
+            (self.sum - t) + sum
+        } else {
+            (sum - t) + self.sum
+        };
+        Compensated {
+            sum: t,
+            err: self.err + err + lost,
+        }
+    }
+}
+
+impl<F: Float> From<F> for Compensated<F> {
+    fn from(x: F) -> Self {
+        Compensated::new(x)
+    }
+}
+
+impl<F: Float> PartialEq for Compensated<F> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value() == other.value()
+    }
+}
+
+impl<F: Float> PartialOrd for Compensated<F> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.value().partial_cmp(&other.value())
+    }
+}
+
+impl<F: Float> Monoid for Compensated<F> {
+    fn identity() -> Self {
+        Compensated::new(F::zero())
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        self.add(other.sum, other.err)
+    }
+}
+
+impl<F: Float> Group for Compensated<F> {
+    fn combine_inverse(&self, other: &Self) -> Self {
+        self.add(-other.sum, -other.err)
+    }
+}
@@ -1,12 +1,243 @@
 extern crate num_traits;
-use num_traits::Zero;
-use std::ops::{Add, Sub};
+use num_traits::{CheckedAdd, CheckedSub, PrimInt};
+use std::ops::{Bound, Range, RangeBounds};
 use std::mem;
 use std::cmp;
-use std::fmt::Debug;
+use std::borrow::Borrow;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::iter::{self, FromIterator};
+use std::marker::PhantomData;
 
+use algebra::*;
 use cmap::*;
 
+/// Clamp a range of integer keys to the half-open extent `[l, r)`,
+/// returning the equivalent half-open range `[lo, hi)`.
+pub(crate) fn clamp_range<K, R>(range: R, l: K, r: K) -> (K, K)
+where
+    K: PrimInt,
+    R: RangeBounds<K>,
+{
+    let lo = match range.start_bound() {
+        Bound::Included(&x) => cmp::max(cmp::min(x, r), l),
+        Bound::Excluded(&x) if x < l => l,
+        Bound::Excluded(&x) if x >= r => r,
+        Bound::Excluded(&x) => x + K::one(),
+        Bound::Unbounded => l,
+    };
+    let hi = match range.end_bound() {
+        Bound::Included(&x) if x < l => l,
+        Bound::Included(&x) if x >= r => r,
+        Bound::Included(&x) => x + K::one(),
+        Bound::Excluded(&x) => cmp::max(cmp::min(x, r), l),
+        Bound::Unbounded => r,
+    };
+    (lo, hi)
+}
+
+/// Get the number of keys from `from` up to `to`, which must be no less than
+/// `from`, or `None` if that does not fit in a `usize`. Unlike `to - from`,
+/// this cannot overflow the key type.
+pub(crate) fn key_distance<K: PrimInt>(from: K, to: K) -> Option<usize> {
+    match to.checked_sub(&from) {
+        Some(d) => d.to_usize(),
+        // Only signed keys with `from < 0 <= to` get here; `-(from + 1)`
+        // cannot overflow even when `from` is the smallest key.
+        None => (K::zero() - (from + K::one()))
+            .to_usize()?
+            .checked_add(to.to_usize()?)?
+            .checked_add(1),
+    }
+}
+
+/// Get the key `i` places after `from`, or `None` if that is beyond the
+/// largest key.
+pub(crate) fn key_offset<K: PrimInt>(from: K, i: usize) -> Option<K> {
+    match K::from(i) {
+        Some(d) => from.checked_add(&d),
+        // `i` is beyond the largest key, so only a negative `from` can reach
+        // a key within range.
+        None if from < K::zero() => K::from(i - key_distance(from, K::zero())?),
+        None => None,
+    }
+}
+
+/// Get the position of `key` relative to `offset`, or `None` if `key` is
+/// below `offset`. Positions which do not fit in a `usize` are given as
+/// `usize::MAX`, which is beyond the capacity of any tree.
+pub(crate) fn key_position<K: PrimInt>(key: K, offset: K) -> Option<usize> {
+    if key < offset {
+        None
+    } else {
+        Some(key_distance(offset, key).unwrap_or(usize::MAX))
+    }
+}
+
+/// Get the key `i` places before `from`, or `None` if that is below the
+/// smallest key.
+pub(crate) fn key_before<K: PrimInt>(from: K, i: usize) -> Option<K> {
+    match key_distance(K::min_value(), from) {
+        Some(room) if i > room => None,
+        Some(room) => key_offset(K::min_value(), room - i),
+        // There are more keys below `from` than any `usize`, so `i` must fit
+        // in the key type.
+        None => from.checked_sub(&K::from(i)?),
+    }
+}
+
+/// Whether every key of the extent `[offset, offset + capacity)` fits in the
+/// key type.
+pub(crate) fn extent_fits<K: PrimInt>(offset: K, capacity: usize) -> bool {
+    capacity == 0 || key_offset(offset, capacity - 1).is_some()
+}
+
+/// Convert a range of keys to the equivalent range of positions relative to
+/// `offset`, ready to be clamped by `clamp_range`.
+pub(crate) fn position_bounds<K, R>(range: &R, offset: K) -> (Bound<usize>, Bound<usize>)
+where
+    K: PrimInt,
+    R: RangeBounds<K>,
+{
+    let lo = match range.start_bound() {
+        Bound::Included(&x) => key_position(x, offset).map_or(Bound::Unbounded, Bound::Included),
+        Bound::Excluded(&x) => key_position(x, offset).map_or(Bound::Unbounded, Bound::Excluded),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    let hi = match range.end_bound() {
+        Bound::Included(&x) => key_position(x, offset).map_or(Bound::Excluded(0), Bound::Included),
+        Bound::Excluded(&x) => key_position(x, offset).map_or(Bound::Excluded(0), Bound::Excluded),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    (lo, hi)
+}
+
+/*****************************************************************************
+ * Queries over Fenwick tree nodes, wherever they are stored
+ *****************************************************************************/
+
+// Each of these takes the number of nodes, `capacity`, and a function `node`
+// giving the node at each position, so that they can be run both over an
+// owned `FenwickTree` and over a borrowed snapshot of one.
+
+/// Get the positions of the nodes which must be updated when the value at
+/// `key` changes. This is empty if `key` is beyond `capacity`.
+pub(crate) fn covering_nodes(capacity: usize, key: usize) -> impl Iterator<Item = usize> {
+    let next = |&k: &usize| if k == 0 { None } else { Some(k + (1 << k.trailing_zeros())) };
+    iter::successors(Some(key), next).take_while(move |&k| k < capacity)
+}
+
+/// Get the positions of the nodes which sum to the cumulative value at
+/// `key`, which must be within capacity.
+pub(crate) fn prefix_nodes(key: usize) -> impl Iterator<Item = usize> {
+    let next = |&k: &usize| if k == 0 { None } else { Some(k & (k - 1)) };
+    iter::successors(Some(key), next)
+}
+
+/// Get the sum of the values at keys up to and including `key`.
+pub(crate) fn prefix_sum<V, B, F>(capacity: usize, node: F, key: usize) -> V
+where
+    V: Group,
+    B: Borrow<V>,
+    F: Fn(usize) -> B,
+{
+    if capacity == 0 {
+        return V::identity();
+    }
+    prefix_nodes(cmp::min(key, capacity - 1))
+        .fold(V::identity(), |sum, i| sum.combine(node(i).borrow()))
+}
+
+/// Get the value at `key` alone.
+pub(crate) fn single_value<V, B, F>(capacity: usize, node: F, key: usize) -> V
+where
+    V: Group,
+    B: Borrow<V>,
+    F: Fn(usize) -> B,
+{
+    if key >= capacity {
+        return V::identity();
+    }
+    let mut val = node(key).borrow().clone();
+    if key == 0 {
+        return val;
+    }
+    let parent = key & (key - 1);
+    let mut key = key - 1;
+    while parent != key {
+        val = val.combine_inverse(node(key).borrow());
+        key &= key - 1;
+    }
+    val
+}
+
+/// Get the sum of the values at keys in `[lo, hi)`, walking the two prefix
+/// sums down only until they meet.
+pub(crate) fn range_sum<V, B, F>(capacity: usize, node: F, lo: usize, hi: usize) -> V
+where
+    V: Group,
+    B: Borrow<V>,
+    F: Fn(usize) -> B,
+{
+    if lo >= hi {
+        return V::identity();
+    }
+    if lo == 0 {
+        return prefix_sum(capacity, node, hi - 1);
+    }
+    let mut add = V::identity();
+    let mut sub = V::identity();
+    let mut i = hi - 1;
+    let mut j = lo - 1;
+    while i != j {
+        if i > j {
+            add = add.combine(node(i).borrow());
+            i &= i - 1;
+        } else {
+            sub = sub.combine(node(j).borrow());
+            j &= j - 1;
+        }
+    }
+    add.combine_inverse(&sub)
+}
+
+/// Get the first key at which the cumulative value reaches `quant`, as
+/// described for `CumlMap::get_quantile`.
+pub(crate) fn quantile<V, B, F>(capacity: usize, node: F, quant: V) -> Option<usize>
+where
+    V: Group + PartialOrd,
+    B: Borrow<V>,
+    F: Fn(usize) -> B,
+{
+    if capacity == 0 || quant.partial_cmp(&quant).is_none() {
+        return None;
+    }
+    let first = node(0);
+    if first.borrow().partial_cmp(&quant) != Some(cmp::Ordering::Less) {
+        return Some(0);
+    }
+    let mut step = capacity.next_power_of_two() >> 1;
+    let mut ix = 0;
+    let mut quant = quant.combine_inverse(first.borrow());
+    while step > 0 {
+        if ix + step < capacity {
+            let n = node(ix + step);
+            if *n.borrow() < quant {
+                ix += step;
+                quant = quant.combine_inverse(n.borrow());
+            }
+        }
+        step >>= 1;
+    }
+    if quant == V::identity() {
+        Some(ix)
+    } else if ix + 1 < capacity {
+        Some(ix + 1)
+    } else {
+        None
+    }
+}
+
 /*****************************************************************************
  * Binary Index Tree, per Peter Fenwick
  *****************************************************************************/
@@ -14,101 +245,618 @@ use cmap::*;
 /// A Fenwick Tree[^fn] structure, useful for very quickly mapping a
 /// non-negative integer key to a cumulative value.
 ///
+/// Keys may be of any primitive integer type `K`, `usize` by default. The
+/// tree covers the keys `0..capacity`, so the capacity is limited to one more
+/// than the largest key, and negative keys are never within it.
+///
+/// Two trees compare equal if they hold the same value at every key, with
+/// keys beyond a tree's capacity counting as zero, and `Debug` prints only
+/// the keys with non-zero values.
+///
+/// # Overflow
+/// Each node of the tree holds the sum of a run of values, so a node may
+/// overflow even when no single value does. How integer values behave then
+/// depends on the value type, which acts as the tree's arithmetic policy:
+///
+/// - Plain integers panic in debug builds and wrap in release builds.
+/// - `Checked<T>` panics in every build, so every result is exact if the tree
+///   is usable at all. `CheckedCumlMap` instead reports overflow as an error.
+/// - `std::num::Wrapping<T>` wraps around. `get_cuml` and `get_range` give
+///   the true sum modulo the size of the type, and `get_single`, `set` and
+///   `remove` stay exact, since wrapping arithmetic is still a group. Once a
+///   cumulative value has wrapped, though, cumulative values are no longer
+///   ordered by key, and the answer of `get_quantile` is meaningless; it is
+///   exact only while the total of every value has not wrapped.
+/// - `std::num::Saturating<T>` stops at the bounds of the type. While only
+///   non-negative values are inserted, `get_cuml`, `get_range` from key 0
+///   and `get_quantile` stay exact up to the maximum, and then report it, so
+///   `get_quantile(MAX)` gives the first key at which the total saturates.
+///   `get_single` is exact only while the cumulative value at its key is
+///   below the maximum, and may under-report after that; `set`, `remove`, and
+///   ranges not starting at 0 subtract from saturated sums, so are likewise
+///   inexact past that point.
+///
+/// # Examples
+/// ```
+/// use cuml_map::{CumlMap, FenwickTree};
+/// use std::num::Saturating;
+///
+/// let mut bytes: FenwickTree<Saturating<u32>> = FenwickTree::with_capacity(4);
+/// bytes.insert(0, Saturating(u32::MAX - 10));
+/// bytes.insert(2, Saturating(100));
+/// assert_eq!(bytes.get_cuml(1), Saturating(u32::MAX - 10));
+/// assert_eq!(bytes.get_cuml(3), Saturating(u32::MAX));
+/// assert_eq!(bytes.get_quantile(Saturating(u32::MAX)), Some(2));
+/// ```
+///
 /// [^fn]: Peter M. Fenwick (1994). "A new data structure for cumulative
 /// frequency tables" (PDF). Software: Practice and Experience. 24 (3): 327–336.
 /// CiteSeerX 10.1.1.14.8917 Freely accessible. doi:10.1002/spe.4380240306
-pub struct FenwickTree<V> {
-    capacity: usize,
-    data: Vec<V>,
+#[derive(Clone)]
+pub struct FenwickTree<V, K = usize> {
+    pub(crate) capacity: usize,
+    pub(crate) data: Vec<V>,
+    pub(crate) keys: PhantomData<K>,
 }
 
-impl<V> FenwickTree<V>
+impl<V, K> FenwickTree<V, K>
 where
-    V: Add<Output = V> + Sub<Output = V> + Zero + Copy + Ord,
+    V: Group,
+    K: PrimInt,
 {
     /// Create a `FenwickTree` object with a fixed capacity.
+    ///
+    /// # Panics
+    /// Panics if the last key, `c - 1`, does not fit in the key type.
     /// 
     /// # Examples
     /// ```
     /// use cuml_map::FenwickTree as FT;
     /// 
     /// let ft: FT<u32> = FT::with_capacity(1234);
+    /// let ports: FT<u32, u16> = FT::with_capacity(65536);
+    /// ```
+    pub fn with_capacity(c: usize) -> FenwickTree<V, K> {
+        Self::from_singles(vec![V::identity(); c])
+    }
+
+    /// Create a `FenwickTree` object whose capacity is the length of the
+    /// given slice, and whose value at each key `i` is `vals[i]`. This runs
+    /// in linear time, rather than the `O(n log n)` of repeated insertion.
+    ///
+    /// # Examples
+    /// ```
+    /// use cuml_map::{CumlMap, FenwickTree as FT};
+    ///
+    /// let ft: FT<u32> = FT::from_slice(&[1, 0, 4, 2]);
+    /// assert_eq!(ft.get_cuml(2), 5);
     /// ```
-    pub fn with_capacity(c: usize) -> FenwickTree<V> {
+    pub fn from_slice(vals: &[V]) -> FenwickTree<V, K> {
+        Self::from_singles(vals.to_vec())
+    }
+
+    /// Build a tree in place from a vector of single values, by pushing
+    /// each node's total up to its parent once the node is complete.
+    fn from_singles(data: Vec<V>) -> FenwickTree<V, K> {
+        let mut data = data;
+        let capacity = data.len();
+        assert!(
+            extent_fits(K::zero(), capacity),
+            "Trying to create a FenwickTree with keys beyond its key type"
+        );
+        for i in 1..capacity {
+            let parent = i + (1 << i.trailing_zeros());
+            if parent < capacity {
+                data[parent] = data[parent].combine(&data[i]);
+            }
+        }
         FenwickTree {
-            capacity: c,
-            data: vec![V::zero(); c],
+            capacity,
+            data,
+            keys: PhantomData,
+        }
+    }
+
+    /// Recover the single value at every key, undoing `from_singles`.
+    fn into_singles(self) -> Vec<V> {
+        let mut data = self.data;
+        for i in (1..self.capacity).rev() {
+            let parent = i + (1 << i.trailing_zeros());
+            if parent < self.capacity {
+                data[parent] = data[parent].combine_inverse(&data[i]);
+            }
+        }
+        data
+    }
+
+    /// Add the value at every key of `other` to the value at the same key in
+    /// this tree, in time linear in the capacity of `other`.
+    ///
+    /// # Panics
+    /// Panics if `other` has a greater capacity than this tree.
+    ///
+    /// # Examples
+    /// ```
+    /// use cuml_map::{CumlMap, FenwickTree as FT};
+    ///
+    /// let mut ft: FT<u32> = FT::from_slice(&[1, 0, 4, 2, 0]);
+    /// ft.merge(&FT::from_slice(&[0, 3, 1]));
+    /// assert_eq!(ft.get_single(1), 3);
+    /// assert_eq!(ft.get_cuml(4), 11);
+    /// ```
+    pub fn merge(&mut self, other: &Self) {
+        self.merge_with(other, V::combine);
+    }
+
+    /// Add the value at every key of `other` to the value at the same key in
+    /// this tree. This is the same as `merge`, under the name shared with the
+    /// other maps in this crate.
+    ///
+    /// # Panics
+    /// Panics if `other` has a greater capacity than this tree.
+    pub fn merge_from(&mut self, other: &Self) {
+        self.merge(other);
+    }
+
+    /// Subtract the value at every key of `other` from the value at the same
+    /// key in this tree, in time linear in the capacity of `other`.
+    ///
+    /// # Panics
+    /// Panics if `other` has a greater capacity than this tree.
+    ///
+    /// # Examples
+    /// ```
+    /// use cuml_map::{CumlMap, FenwickTree as FT};
+    ///
+    /// let mut ft: FT<i32> = FT::from_slice(&[1, 0, 4, 2, 0]);
+    /// ft.subtract(&FT::from_slice(&[0, 3, 1]));
+    /// assert_eq!(ft.get_single(1), -3);
+    /// assert_eq!(ft.get_cuml(4), 3);
+    /// ```
+    pub fn subtract(&mut self, other: &Self) {
+        self.merge_with(other, V::combine_inverse);
+    }
+
+    /// Replace the value `x` at every key with `f(x, y)`, where `y` is the
+    /// value at the same key of `other`. This relies on `f` distributing
+    /// over sums, as both `combine` and `combine_inverse` do.
+    pub(crate) fn merge_with<F>(&mut self, other: &Self, f: F)
+    where
+        F: Fn(&V, &V) -> V,
+    {
+        let c = other.capacity;
+        assert!(c <= self.capacity, "Trying to merge a larger tree into a smaller one");
+        for (x, y) in self.data.iter_mut().zip(&other.data) {
+            *x = f(x, y);
         }
+        // Any node beyond the end of `other` which covers some of its keys
+        // also covers its last key, so lies on that key's update path.
+        if c > 1 {
+            for i in covering_nodes(self.capacity, c - 1).filter(|&i| i >= c) {
+                let lo = i + 1 - (1 << i.trailing_zeros());
+                self.data[i] = f(&self.data[i], &other.sum_between(lo, c));
+            }
+        }
+    }
+
+    /// Apply `f` to every node in the tree that covers `key`.
+    fn update<F>(&mut self, key: usize, f: F)
+    where
+        F: Fn(&V) -> V,
+    {
+        assert!(key < self.capacity);
+        for i in covering_nodes(self.capacity, key) {
+            self.data[i] = f(&self.data[i]);
+        }
+    }
+
+    /// Get the sum of the values at keys in `[lo, hi)`.
+    fn sum_between(&self, lo: usize, hi: usize) -> V {
+        range_sum(self.capacity, |i| &self.data[i], lo, hi)
+    }
+
+    /// Get the value at position `i` alone.
+    fn single(&self, i: usize) -> V {
+        single_value(self.capacity, |i| &self.data[i], i)
+    }
+
+    /// Get the position of `key` in the tree, if it is within capacity.
+    fn position(&self, key: K) -> Option<usize> {
+        key_position(key, K::zero()).filter(|&i| i < self.capacity)
+    }
+
+    /// Get the position of `key` in the tree, panicking if it is beyond the
+    /// tree's capacity.
+    fn index(&self, key: K) -> usize {
+        self.position(key).expect("Trying to update a key beyond the tree's capacity")
+    }
+
+    /// Get the key at position `i`, which always fits in the key type, since
+    /// the tree's capacity was checked on creation.
+    fn key(i: usize) -> K {
+        K::from(i).unwrap()
+    }
+
+    /// Iterate over every `(key, value)` pair in the tree, in key order.
+    ///
+    /// # Examples
+    /// ```
+    /// use cuml_map::{CumlMap, FenwickTree as FT};
+    ///
+    /// let mut ft: FT<u32> = FT::with_capacity(4);
+    /// ft.insert(1, 3);
+    /// ft.insert(3, 2);
+    /// let v: Vec<_> = ft.iter().collect();
+    /// assert_eq!(v, vec![(0, 0), (1, 3), (2, 0), (3, 2)]);
+    /// let v: Vec<_> = ft.iter().skip_zeros().cumulative().collect();
+    /// assert_eq!(v, vec![(1, 3), (3, 5)]);
+    /// ```
+    pub fn iter(&self) -> FenwickIter<'_, V, K> {
+        self.range(..)
+    }
+
+    /// Iterate over every `(key, cumulative value)` pair in the tree, in
+    /// key order.
+    pub fn cuml_iter(&self) -> Cumulative<FenwickIter<'_, V, K>, V> {
+        self.iter().cumulative()
+    }
+
+    /// Iterate over the `(key, value)` pairs in the tree whose keys lie
+    /// within the given range, in key order.
+    pub fn range<R>(&self, range: R) -> FenwickIter<'_, V, K>
+    where
+        R: RangeBounds<K>,
+    {
+        let (lo, hi) = clamp_range(position_bounds(&range, K::zero()), 0, self.capacity);
+        FenwickIter {
+            tree: self,
+            front: lo,
+            back: cmp::max(lo, hi),
+            skip_zeros: false,
+        }
+    }
+}
+
+impl<V, K> FromIterator<V> for FenwickTree<V, K>
+where
+    V: Group,
+    K: PrimInt,
+{
+    /// Collect values into a `FenwickTree`, with the `i`th value yielded
+    /// stored at key `i`. Like `from_slice`, this runs in linear time.
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = V>,
+    {
+        Self::from_singles(iter.into_iter().collect())
+    }
+}
+
+impl<V: Group, K: PrimInt> Default for FenwickTree<V, K> {
+    /// Create a `FenwickTree` with zero capacity.
+    fn default() -> Self {
+        Self::with_capacity(0)
+    }
+}
+
+impl<V, K> fmt::Debug for FenwickTree<V, K>
+where
+    V: Group + fmt::Debug,
+    K: PrimInt + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter().skip_zeros()).finish()
+    }
+}
+
+impl<V: Group, K: PrimInt> PartialEq for FenwickTree<V, K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().skip_zeros().eq(other.iter().skip_zeros())
     }
 }
 
-impl<V> CumlMap for FenwickTree<V>
+impl<V: Group + Eq, K: PrimInt> Eq for FenwickTree<V, K> {}
+
+impl<V: Group + Hash, K: PrimInt + Hash> Hash for FenwickTree<V, K> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_entries(self.iter().skip_zeros(), state)
+    }
+}
+
+/// An iterator over the `(key, value)` pairs of a `FenwickTree`.
+pub struct FenwickIter<'a, V: 'a, K: 'a = usize> {
+    tree: &'a FenwickTree<V, K>,
+    front: usize,
+    back: usize,
+    skip_zeros: bool,
+}
+
+impl<'a, V, K> FenwickIter<'a, V, K>
 where
-    V: Add<Output = V> + Sub<Output = V> + Zero + Copy + Ord,
+    V: Group,
+    K: PrimInt,
 {
-    type Key = usize;
+    /// Skip any keys whose value is zero.
+    pub fn skip_zeros(self) -> Self {
+        FenwickIter {
+            skip_zeros: true,
+            ..self
+        }
+    }
+
+    /// Yield cumulative values rather than single values.
+    pub fn cumulative(self) -> Cumulative<Self, V> {
+        let (front, back) = self.bounds();
+        Cumulative::new(self, front, back)
+    }
+
+    /// Get the cumulative values before the front and the back of the
+    /// iterator, from which `Cumulative` picks up.
+    pub(crate) fn bounds(&self) -> (V, V) {
+        (self.tree.sum_between(0, self.front), self.tree.sum_between(0, self.back))
+    }
+}
+
+impl<'a, V, K> Iterator for FenwickIter<'a, V, K>
+where
+    V: Group,
+    K: PrimInt,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.front < self.back {
+            let i = self.front;
+            self.front += 1;
+            let val = self.tree.single(i);
+            if !self.skip_zeros || val != V::identity() {
+                return Some((FenwickTree::<V, K>::key(i), val));
+            }
+        }
+        None
+    }
+}
+
+impl<'a, V, K> DoubleEndedIterator for FenwickIter<'a, V, K>
+where
+    V: Group,
+    K: PrimInt,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        while self.front < self.back {
+            self.back -= 1;
+            let i = self.back;
+            let val = self.tree.single(i);
+            if !self.skip_zeros || val != V::identity() {
+                return Some((FenwickTree::<V, K>::key(i), val));
+            }
+        }
+        None
+    }
+}
+
+impl<V, K> CumlMap for FenwickTree<V, K>
+where
+    V: Group,
+    K: PrimInt,
+{
+    type Key = K;
     type Value = V;
 
     fn insert(&mut self, key: Self::Key, val: Self::Value) {
-        assert!(key < self.capacity);
-        let mut key = key;
-        while key < self.capacity {
-            self.data[key as usize] = self.data[key as usize] + val;
-            if key == 0 {
-                break;
+        self.update(self.index(key), |x| x.combine(&val));
+    }
+
+    fn set(&mut self, key: Self::Key, val: Self::Value) {
+        let old = self.get_single(key);
+        self.update(self.index(key), |x| x.combine_inverse(&old).combine(&val));
+    }
+
+    fn remove(&mut self, key: Self::Key) -> Self::Value {
+        match self.position(key) {
+            None => V::identity(),
+            Some(i) => {
+                let old = self.single(i);
+                self.update(i, |x| x.combine_inverse(&old));
+                old
             }
-            key += 1 << key.trailing_zeros();
         }
     }
 
     fn get_cuml(&self, key: Self::Key) -> Self::Value {
-        let mut key = cmp::min(key, self.capacity - 1);
-        let mut sum = self.data[0];
-        while key > 0 {
-            sum = sum + self.data[key];
-            key = key & (key - 1);
+        match key_position(key, K::zero()) {
+            None => V::identity(),
+            Some(i) => prefix_sum(self.capacity, |i| &self.data[i], i),
         }
-        sum
     }
 
     fn get_single(&self, key: Self::Key) -> Self::Value {
-        if key >= self.capacity { return Self::Value::zero() }
-        let mut val = self.data[key];
-        let mut key = key;
-        if key == 0 {
-            return val;
-        }
-        let parent = key & (key - 1);
-        key -= 1;
-        while parent != key {
-            val = val - self.data[key];
-            key = key & (key - 1);
-        }
-        val
+        self.position(key).map_or_else(V::identity, |i| self.single(i))
+    }
+
+    fn get_range<R>(&self, range: R) -> Self::Value
+    where
+        R: RangeBounds<Self::Key>,
+    {
+        let (lo, hi) = clamp_range(position_bounds(&range, K::zero()), 0, self.capacity);
+        self.sum_between(lo, hi)
     }
 
-    fn get_quantile(&self, quant: Self::Value) -> Option<Self::Key> {
-        if quant <= self.data[0] {
-            Some(0)
+    fn get_quantile(&self, quant: Self::Value) -> Option<Self::Key>
+    where
+        V: PartialOrd,
+    {
+        quantile(self.capacity, |i| &self.data[i], quant).map(Self::key)
+    }
+}
+
+/// Get the half-open range of keys `[lo, hi)` within `range`, or an error if
+/// it reaches beyond the keys `[0, capacity)`.
+fn checked_range<K, R>(range: R, capacity: usize) -> Result<(usize, usize), Error>
+where
+    K: PrimInt,
+    R: RangeBounds<K>,
+{
+    if capacity == 0 {
+        return Err(Error::ZeroCapacity);
+    }
+    // Any range reaching a negative key reaches beyond capacity.
+    let position = |x| key_position(x, K::zero()).ok_or(Error::KeyOutOfRange);
+    let lo = match range.start_bound() {
+        Bound::Included(&x) => position(x)?,
+        Bound::Excluded(&x) if x < K::zero() => position(x + K::one())?,
+        Bound::Excluded(&x) => position(x)?.saturating_add(1),
+        Bound::Unbounded => 0,
+    };
+    let hi = match range.end_bound() {
+        Bound::Included(&x) => position(x)?.checked_add(1).ok_or(Error::KeyOutOfRange)?,
+        Bound::Excluded(&x) => position(x)?,
+        Bound::Unbounded => capacity,
+    };
+    if hi > capacity {
+        return Err(Error::KeyOutOfRange);
+    }
+    Ok((cmp::min(lo, hi), hi))
+}
+
+/// Turn the result of a query run with checked arithmetic into an error if
+/// it overflowed.
+fn checked_result<V>(c: CheckedSum<V>) -> Result<V, Error> {
+    c.0.ok_or(Error::Overflow)
+}
+
+impl<V, K> FenwickTree<V, K>
+where
+    V: Group,
+    K: PrimInt,
+{
+    /// Check that `key` is one which the tree can hold, returning its
+    /// position.
+    fn check_key(&self, key: K) -> Result<usize, Error> {
+        if self.capacity == 0 {
+            Err(Error::ZeroCapacity)
         } else {
-            let mut step = self.capacity.next_power_of_two() >> 1;
-            let mut ix = 0;
-            let mut quant = quant - self.data[0];
-            while step > 0 {
-                if ix + step < self.capacity && self.data[ix + step] < quant {
-                    ix += step;
-                    quant = quant - self.data[ix];
-                }
-                step >>= 1;
-            }
-            if quant == Self::Value::zero() {
-                Some(ix)
-            } else if ix + 1 < self.capacity {
-                Some(ix + 1)
-            } else {
-                None
-            }
+            self.position(key).ok_or(Error::KeyOutOfRange)
+        }
+    }
+
+    /// Insert an entry into the tree, or return an error rather than
+    /// panicking if `key` is beyond its capacity. This does not check for
+    /// overflow; see `CheckedCumlMap::checked_insert` for that.
+    ///
+    /// # Examples
+    /// ```
+    /// use cuml_map::{Error, FenwickTree};
+    ///
+    /// let mut ft: FenwickTree<f64> = FenwickTree::with_capacity(4);
+    /// assert_eq!(ft.try_insert(3, 1.5), Ok(()));
+    /// assert_eq!(ft.try_insert(4, 1.5), Err(Error::KeyOutOfRange));
+    /// assert_eq!(FenwickTree::with_capacity(0).try_get_cuml(0), Err::<f64, _>(Error::ZeroCapacity));
+    /// ```
+    pub fn try_insert(&mut self, key: K, val: V) -> Result<(), Error> {
+        self.check_key(key)?;
+        self.insert(key, val);
+        Ok(())
+    }
+
+    /// Set the value at `key`, or return an error rather than panicking if
+    /// `key` is beyond the tree's capacity.
+    pub fn try_set(&mut self, key: K, val: V) -> Result<(), Error> {
+        self.check_key(key)?;
+        self.set(key, val);
+        Ok(())
+    }
+
+    /// Get the cumulative value at `key`, or return an error if `key` is
+    /// beyond the tree's capacity, rather than the total of every value.
+    pub fn try_get_cuml(&self, key: K) -> Result<V, Error> {
+        self.check_key(key)?;
+        Ok(self.get_cuml(key))
+    }
+
+    /// Get the value at `key`, or return an error if `key` is beyond the
+    /// tree's capacity, rather than zero.
+    pub fn try_get_single(&self, key: K) -> Result<V, Error> {
+        self.check_key(key)?;
+        Ok(self.get_single(key))
+    }
+
+    /// Get the sum of the values at keys within `range`, or return an error
+    /// if `range` reaches beyond the tree's capacity, rather than ignoring
+    /// the part of it which does.
+    pub fn try_get_range<R>(&self, range: R) -> Result<V, Error>
+    where
+        R: RangeBounds<K>,
+    {
+        let (lo, hi) = checked_range(range, self.capacity)?;
+        Ok(self.sum_between(lo, hi))
+    }
+
+    /// Replace every node covering `key` with `f` of it, unless `f` fails
+    /// for any of them, in which case the tree is left unchanged.
+    fn try_update<F>(&mut self, key: K, f: F) -> Result<(), Error>
+    where
+        F: Fn(&V) -> Option<V>,
+    {
+        let key = self.check_key(key)?;
+        let nodes: Option<Vec<(usize, V)>> = covering_nodes(self.capacity, key)
+            .map(|i| f(&self.data[i]).map(|v| (i, v)))
+            .collect();
+        for (i, v) in nodes.ok_or(Error::Overflow)? {
+            self.data[i] = v;
         }
+        Ok(())
+    }
+}
+
+impl<V, K> CheckedCumlMap for FenwickTree<V, K>
+where
+    V: Group + CheckedAdd + CheckedSub,
+    K: PrimInt,
+{
+    /// # Examples
+    /// ```
+    /// use cuml_map::{CheckedCumlMap, CumlMap, Error, FenwickTree};
+    ///
+    /// let mut ft: FenwickTree<u8> = FenwickTree::with_capacity(4);
+    /// ft.insert(1, 200);
+    /// assert_eq!(ft.checked_insert(2, 100), Err(Error::Overflow));
+    /// assert_eq!(ft.get_cuml(3), 200);
+    /// ft.insert(0, 50);
+    /// assert_eq!(ft.checked_get_cuml(0), Ok(50));
+    /// assert_eq!(ft.checked_get_single(3), Ok(0));
+    /// ```
+    fn checked_insert(&mut self, key: Self::Key, val: Self::Value) -> Result<(), Error> {
+        self.try_update(key, |x| x.checked_add(&val))
+    }
+
+    fn checked_set(&mut self, key: Self::Key, val: Self::Value) -> Result<(), Error> {
+        let old = self.checked_get_single(key)?;
+        self.try_update(key, |x| x.checked_sub(&old)?.checked_add(&val))
+    }
+
+    fn checked_remove(&mut self, key: Self::Key) -> Result<Self::Value, Error> {
+        let old = self.checked_get_single(key)?;
+        self.try_update(key, |x| x.checked_sub(&old))?;
+        Ok(old)
+    }
+
+    fn checked_get_cuml(&self, key: Self::Key) -> Result<Self::Value, Error> {
+        let key = self.check_key(key)?;
+        checked_result(prefix_sum(self.capacity, |i| CheckedSum::of(&self.data[i]), key))
+    }
+
+    fn checked_get_single(&self, key: Self::Key) -> Result<Self::Value, Error> {
+        let key = self.check_key(key)?;
+        checked_result(single_value(self.capacity, |i| CheckedSum::of(&self.data[i]), key))
+    }
+
+    fn checked_get_range<R>(&self, range: R) -> Result<Self::Value, Error>
+    where
+        R: RangeBounds<Self::Key>,
+    {
+        let (lo, hi) = checked_range(range, self.capacity)?;
+        checked_result(range_sum(self.capacity, |i| CheckedSum::of(&self.data[i]), lo, hi))
     }
 }
 
@@ -122,14 +870,40 @@ where
 /// that is outside the bounds of the underlying `FenwickTree`, then a new
 /// `FenwickTree` is created with sufficient capacity, all entries from the
 /// old tree are inserted into the new tree, and the old tree is dropped.
-pub struct ExtensibleFenwickTree<V> {
-    offset: i64, // minimum possible key in mapping
+///
+/// As with `FenwickTree`, equality and `Debug` consider only the value at
+/// each key, and not the extent the tree happens to cover.
+///
+/// Keys may be of any primitive integer type `K`, `i64` by default. The tree
+/// only ever grows to cover keys which fit in that type, and converts keys
+/// to positions in the underlying tree without overflow, however far apart
+/// they are.
+///
+/// Overflow is handled according to the value type, as described for
+/// `FenwickTree`. Since growing the tree rebuilds it from the value at each
+/// key, a tree of `std::num::Saturating` values which grows after saturating
+/// keeps only the values which `get_single` would have reported.
+///
+/// # Examples
+/// ```
+/// use cuml_map::{CumlMap, ExtensibleFenwickTree as EFT};
+///
+/// let mut deltas: EFT<u32, i8> = EFT::new();
+/// deltas.insert(-128, 1);
+/// deltas.insert(127, 2);
+/// assert_eq!(deltas.get_cuml(0), 1);
+/// assert_eq!(deltas.get_range(-100..), 2);
+/// ```
+#[derive(Clone)]
+pub struct ExtensibleFenwickTree<V, K = i64> {
+    offset: K, // minimum possible key in mapping
     tree: FenwickTree<V>,
 }
 
-impl<V> ExtensibleFenwickTree<V>
+impl<V, K> ExtensibleFenwickTree<V, K>
 where
-    V: Add<Output = V> + Sub<Output = V> + Zero + Copy + Ord + Debug,
+    V: Group,
+    K: PrimInt,
 {
     /// Creates an empty tree with zero initial capacity.
     /// 
@@ -140,13 +914,13 @@ where
     /// let eft: EFT<u32> = EFT::new();
     /// ```
     pub fn new() -> Self {
-        Self {
-            offset: 0,
-            tree: FenwickTree::with_capacity(0),
-        }
+        Self::with_extent(K::zero(), 0)
     }
 
     /// Creates an empty tree with fixed initial capacity.
+    ///
+    /// # Panics
+    /// Panics if the last key, `c - 1`, does not fit in the key type.
     /// 
     /// # Examples
     /// ```
@@ -155,14 +929,14 @@ where
     /// let eft: EFT<u32> = EFT::with_capacity(1234);
     /// ```
     pub fn with_capacity(c: usize) -> Self {
-        Self {
-            offset: 0,
-            tree: FenwickTree::with_capacity(c),
-        }
+        Self::with_extent(K::zero(), c)
     }
 
     /// Creates an empty tree with a capacity `c` and offset `o`, such that
     /// the tree initially covers the range of keys `[o, o+c)`.
+    ///
+    /// # Panics
+    /// Panics if the last key, `o + c - 1`, does not fit in the key type.
     /// 
     /// # Examples
     /// ```
@@ -170,30 +944,107 @@ where
     /// 
     /// let eft: EFT<u32> = EFT::with_extent(0, 1234);
     /// ```
-    pub fn with_extent(o: i64, c: usize) -> Self {
+    pub fn with_extent(o: K, c: usize) -> Self {
+        assert!(
+            extent_fits(o, c),
+            "Trying to create an ExtensibleFenwickTree with keys beyond its key type"
+        );
         Self {
             offset: o,
             tree: FenwickTree::with_capacity(c),
         }
     }
 
-    fn extent(&self) -> (i64, i64) {
-        (self.offset, self.offset + (self.tree.capacity as i64))
+    /// Get the position of `key` in the underlying tree, if it is within
+    /// the current extent.
+    fn position(&self, key: K) -> Option<usize> {
+        key_position(key, self.offset).filter(|&i| i < self.tree.capacity)
     }
 
-    fn extend(&mut self, by: i64) {
-        let oldcap = self.tree.capacity;
-        let cap = oldcap + by.abs() as usize;
-        let new = FenwickTree::with_capacity(cap);
-        let old = mem::replace(&mut self.tree, new);
-        let oldoff = self.offset;
-        self.offset += cmp::min(by, 0);
+    /// Get the key at position `i` of the underlying tree, which always
+    /// fits in the key type, since the tree only grows to cover keys which
+    /// do.
+    fn key(&self, i: usize) -> K {
+        key_offset(self.offset, i).unwrap()
+    }
 
-        for i in 0..oldcap {
-            self.tree.insert((i as i64 - self.offset + oldoff) as usize, old.get_single(i));
+    /// Ensure that the tree covers `key`, returning its position.
+    fn make_room(&mut self, key: K) -> usize {
+        self.ensure_contains(key);
+        key_distance(self.offset, key).unwrap()
+    }
+
+    /// Rebuild the tree with `below` more keys before its current extent,
+    /// and `above` more after it.
+    fn extend(&mut self, below: usize, above: usize) {
+        let len = self.extended_len(below, above);
+        let old = mem::replace(&mut self.tree, FenwickTree::with_capacity(0));
+        let mut vals = vec![V::identity(); len];
+        for (i, v) in old.into_singles().into_iter().enumerate() {
+            vals[i + below] = v;
+        }
+        self.tree = FenwickTree::from_singles(vals);
+        self.offset = key_before(self.offset, below).unwrap();
+    }
+
+    /// Get the capacity of the tree after adding `below` keys before its
+    /// current extent, and `above` after it.
+    fn extended_len(&self, below: usize, above: usize) -> usize {
+        self.tree
+            .capacity
+            .checked_add(below)
+            .and_then(|c| c.checked_add(above))
+            .expect("Trying to extend an ExtensibleFenwickTree beyond usize::MAX keys")
+    }
+
+    /// Iterate over every `(key, value)` pair in the tree's current extent,
+    /// in key order.
+    ///
+    /// # Examples
+    /// ```
+    /// use cuml_map::{CumlMap, ExtensibleFenwickTree as EFT};
+    ///
+    /// let mut eft: EFT<u32> = EFT::new();
+    /// eft.insert(-4, 3);
+    /// eft.insert(2, 2);
+    /// let v: Vec<_> = eft.iter().skip_zeros().collect();
+    /// assert_eq!(v, vec![(-4, 3), (2, 2)]);
+    /// let v: Vec<_> = eft.iter().skip_zeros().cumulative().rev().collect();
+    /// assert_eq!(v, vec![(2, 5), (-4, 3)]);
+    /// ```
+    pub fn iter(&self) -> ExtensibleFenwickIter<'_, V, K> {
+        self.range(..)
+    }
+
+    /// Iterate over every `(key, cumulative value)` pair in the tree's
+    /// current extent, in key order.
+    pub fn cuml_iter(&self) -> Cumulative<ExtensibleFenwickIter<'_, V, K>, V> {
+        self.iter().cumulative()
+    }
+
+    /// Iterate over the `(key, value)` pairs in the tree whose keys lie
+    /// within both the given range and the tree's current extent, in key
+    /// order.
+    pub fn range<R>(&self, range: R) -> ExtensibleFenwickIter<'_, V, K>
+    where
+        R: RangeBounds<K>,
+    {
+        ExtensibleFenwickIter {
+            iter: self.tree.range(self.positions(&range)),
+            offset: self.offset,
         }
     }
 
+    /// Get the half-open range of positions in the underlying tree of the
+    /// keys within both `range` and the tree's current extent.
+    fn positions<R>(&self, range: &R) -> Range<usize>
+    where
+        R: RangeBounds<K>,
+    {
+        let (lo, hi) = clamp_range(position_bounds(range, self.offset), 0, self.tree.capacity);
+        lo..cmp::max(lo, hi)
+    }
+
     /// Ensures that the tree will cover key `key`, in addition to all keys
     /// previously covered. Reallocates and rebuilds the tree if necessary.
     /// 
@@ -207,50 +1058,453 @@ where
     /// let mut eft2: EFT<u32> = EFT::with_extent(0, 1234);
     /// eft2.ensure_contains(1024);
     /// ```
-    pub fn ensure_contains(&mut self, key: i64) {
-        let (l, r) = self.extent();
-        let extra =
-            if key >= r { key - r + 1 }
-            else if key < l { key - l }
-            else { return };
-        let mut cap = cmp::max(8, self.tree.capacity as i64) * extra.signum();
-        while cap.abs() < extra.abs() {
-            cap *= 2;
+    pub fn ensure_contains(&mut self, key: K) {
+        if let Some((below, above)) = self.growth(key) {
+            self.extend(below, above);
+        }
+    }
+
+    /// Get the number of keys to add before and after the current extent so
+    /// that it covers `key`, or `None` if it already does.
+    fn growth(&self, key: K) -> Option<(usize, usize)> {
+        let cap = self.tree.capacity;
+        // The number of keys needed before or after the current extent, and
+        // the number of keys there are in the key type.
+        let (below, need, room) = match key_position(key, self.offset) {
+            Some(i) if i < cap => return None,
+            Some(i) => {
+                let last = key_distance(self.offset, K::max_value());
+                (false, (i - cap).saturating_add(1), last.map_or(usize::MAX, |d| d.saturating_add(1) - cap))
+            }
+            None => {
+                let first = key_distance(K::min_value(), self.offset);
+                (true, key_distance(key, self.offset).unwrap_or(usize::MAX), first.unwrap_or(usize::MAX))
+            }
+        };
+        let mut extra = cmp::max(8, cap);
+        while extra < need {
+            extra = extra.saturating_mul(2);
+        }
+        let extra = cmp::min(extra, room);
+        Some(if below { (extra, 0) } else { (0, extra) })
+    }
+
+    /// Add the value at every key of `other` to the value at the same key in
+    /// this tree, first extending this tree to cover all of `other`'s
+    /// extent. This takes time linear in the size of the two trees.
+    ///
+    /// # Examples
+    /// ```
+    /// use cuml_map::{CumlMap, ExtensibleFenwickTree as EFT};
+    ///
+    /// let mut a: EFT<i32> = EFT::new();
+    /// a.insert(3, 2);
+    /// let mut b: EFT<i32> = EFT::new();
+    /// b.insert(-5, 1);
+    /// b.insert(3, 4);
+    /// a.merge(&b);
+    /// assert_eq!(a.get_single(3), 6);
+    /// assert_eq!(a.get_cuml(0), 1);
+    /// ```
+    pub fn merge(&mut self, other: &Self) {
+        self.merge_with(other, V::combine);
+    }
+
+    /// Add the value at every key of `other` to the value at the same key in
+    /// this tree. This is the same as `merge`, under the name shared with the
+    /// other maps in this crate.
+    pub fn merge_from(&mut self, other: &Self) {
+        self.merge(other);
+    }
+
+    /// Subtract the value at every key of `other` from the value at the same
+    /// key in this tree, first extending this tree to cover all of `other`'s
+    /// extent. This takes time linear in the size of the two trees.
+    ///
+    /// # Examples
+    /// ```
+    /// use cuml_map::{CumlMap, ExtensibleFenwickTree as EFT};
+    ///
+    /// let mut a: EFT<i32> = EFT::new();
+    /// a.insert(3, 2);
+    /// let mut b: EFT<i32> = EFT::new();
+    /// b.insert(-5, 1);
+    /// b.insert(3, 4);
+    /// a.subtract(&b);
+    /// assert_eq!(a.get_single(3), -2);
+    /// assert_eq!(a.get_cuml(0), -1);
+    /// ```
+    pub fn subtract(&mut self, other: &Self) {
+        self.merge_with(other, V::combine_inverse);
+    }
+
+    /// Replace the value `x` at every key with `f(x, y)`, where `y` is the
+    /// value at the same key of `other`, as for `FenwickTree::merge_with`.
+    fn merge_with<F>(&mut self, other: &Self, f: F)
+    where
+        F: Fn(&V, &V) -> V,
+    {
+        if other.tree.capacity == 0 {
+            return;
+        }
+        self.ensure_contains(other.offset);
+        self.ensure_contains(other.key(other.tree.capacity - 1));
+        let shift = key_distance(self.offset, other.offset).unwrap();
+        if shift == 0 {
+            self.tree.merge_with(&other.tree, f);
+        } else {
+            let tree = mem::replace(&mut self.tree, FenwickTree::with_capacity(0));
+            let mut vals = tree.into_singles();
+            for (i, v) in other.tree.clone().into_singles().into_iter().enumerate() {
+                vals[i + shift] = f(&vals[i + shift], &v);
+            }
+            self.tree = FenwickTree::from_singles(vals);
         }
-        self.extend(cap);
     }
 }
 
-impl<V> CumlMap for ExtensibleFenwickTree<V>
+impl<V, K> CumlMap for ExtensibleFenwickTree<V, K>
 where
-    V: Add<Output = V> + Sub<Output = V> + Zero + Copy + Ord + Debug,
+    V: Group,
+    K: PrimInt,
 {
-    type Key = i64;
+    type Key = K;
     type Value = V;
 
     fn insert(&mut self, key: Self::Key, val: Self::Value) {
-        self.ensure_contains(key);
-        self.tree.insert((key - self.offset) as usize, val);
+        let i = self.make_room(key);
+        self.tree.insert(i, val);
+    }
+
+    fn set(&mut self, key: Self::Key, val: Self::Value) {
+        let i = self.make_room(key);
+        self.tree.set(i, val);
+    }
+
+    fn remove(&mut self, key: Self::Key) -> Self::Value {
+        match self.position(key) {
+            None => V::identity(),
+            Some(i) => self.tree.remove(i),
+        }
     }
 
     fn get_cuml(&self, key: Self::Key) -> Self::Value {
-        if self.offset > key {
-            Self::Value::zero()
-        } else {
-            self.tree.get_cuml((key - self.offset) as usize)
+        match key_position(key, self.offset) {
+            None => V::identity(),
+            Some(i) => self.tree.get_cuml(i),
         }
     }
 
     fn get_single(&self, key: Self::Key) -> Self::Value {
-        if self.offset > key {
-            Self::Value::zero()
-        } else {
-            self.tree.get_single((key - self.offset) as usize)
+        match self.position(key) {
+            None => V::identity(),
+            Some(i) => self.tree.get_single(i),
+        }
+    }
+
+    fn get_range<R>(&self, range: R) -> Self::Value
+    where
+        R: RangeBounds<Self::Key>,
+    {
+        let r = self.positions(&range);
+        self.tree.sum_between(r.start, r.end)
+    }
+
+    fn get_quantile(&self, quant: Self::Value) -> Option<Self::Key>
+    where
+        V: PartialOrd,
+    {
+        self.tree.get_quantile(quant).map(|i| self.key(i))
+    }
+}
+
+impl<V, K> ExtensibleFenwickTree<V, K>
+where
+    V: Group + CheckedAdd + CheckedSub,
+    K: PrimInt,
+{
+    /// Build a copy of this tree extended to cover `key`, as
+    /// `ensure_contains` would, or `None` if it already covers `key`. Its
+    /// nodes are summed with checked arithmetic, since the new layout may
+    /// combine values which no existing node does.
+    fn checked_growth(&self, key: K) -> Result<Option<Self>, Error> {
+        let (below, above) = match self.growth(key) {
+            None => return Ok(None),
+            Some(g) => g,
+        };
+        let len = self.extended_len(below, above);
+        let mut vals = vec![CheckedSum::of(&V::identity()); len];
+        for (i, (_, v)) in self.tree.iter().enumerate() {
+            vals[i + below] = CheckedSum(Some(v));
+        }
+        let data = FenwickTree::<CheckedSum<V>>::from_singles(vals)
+            .data
+            .into_iter()
+            .map(|n| n.0)
+            .collect::<Option<Vec<V>>>()
+            .ok_or(Error::Overflow)?;
+        Ok(Some(ExtensibleFenwickTree {
+            offset: key_before(self.offset, below).unwrap(),
+            tree: FenwickTree {
+                capacity: len,
+                data,
+                keys: PhantomData,
+            },
+        }))
+    }
+
+    /// Apply the checked update `f` at the position of `key`, first
+    /// extending the tree to cover it. The tree is left unchanged, extent
+    /// included, if either step fails.
+    fn checked_update<F>(&mut self, key: K, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut FenwickTree<V>, usize) -> Result<(), Error>,
+    {
+        match self.checked_growth(key)? {
+            None => {
+                let i = self.position(key).unwrap();
+                f(&mut self.tree, i)
+            }
+            Some(mut grown) => {
+                let i = grown.position(key).unwrap();
+                f(&mut grown.tree, i)?;
+                *self = grown;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<V, K> CheckedCumlMap for ExtensibleFenwickTree<V, K>
+where
+    V: Group + CheckedAdd + CheckedSub,
+    K: PrimInt,
+{
+    fn checked_insert(&mut self, key: Self::Key, val: Self::Value) -> Result<(), Error> {
+        self.checked_update(key, |tree, i| tree.checked_insert(i, val))
+    }
+
+    fn checked_set(&mut self, key: Self::Key, val: Self::Value) -> Result<(), Error> {
+        self.checked_update(key, |tree, i| tree.checked_set(i, val))
+    }
+
+    fn checked_remove(&mut self, key: Self::Key) -> Result<Self::Value, Error> {
+        match self.position(key) {
+            None => Ok(V::identity()),
+            Some(i) => self.tree.checked_remove(i),
+        }
+    }
+
+    fn checked_get_cuml(&self, key: Self::Key) -> Result<Self::Value, Error> {
+        self.checked_get_range(..=key)
+    }
+
+    fn checked_get_single(&self, key: Self::Key) -> Result<Self::Value, Error> {
+        self.checked_get_range(key..=key)
+    }
+
+    fn checked_get_range<R>(&self, range: R) -> Result<Self::Value, Error>
+    where
+        R: RangeBounds<Self::Key>,
+    {
+        let r = self.positions(&range);
+        if r.is_empty() {
+            return Ok(V::identity());
         }
+        self.tree.checked_get_range(r)
+    }
+}
+
+impl<V: Group, K: PrimInt> Default for ExtensibleFenwickTree<V, K> {
+    fn default() -> Self {
+        Self::new()
     }
+}
+
+impl<V, K> fmt::Debug for ExtensibleFenwickTree<V, K>
+where
+    V: Group + fmt::Debug,
+    K: PrimInt + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter().skip_zeros()).finish()
+    }
+}
 
-    fn get_quantile(&self, quant: Self::Value) -> Option<Self::Key> {
-        self.tree.get_quantile(quant).map(|x| x as i64 + self.offset)
+impl<V: Group, K: PrimInt> PartialEq for ExtensibleFenwickTree<V, K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().skip_zeros().eq(other.iter().skip_zeros())
     }
 }
 
+impl<V: Group + Eq, K: PrimInt> Eq for ExtensibleFenwickTree<V, K> {}
+
+impl<V: Group + Hash, K: PrimInt + Hash> Hash for ExtensibleFenwickTree<V, K> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_entries(self.iter().skip_zeros(), state)
+    }
+}
+
+/// An iterator over the `(key, value)` pairs of an `ExtensibleFenwickTree`.
+pub struct ExtensibleFenwickIter<'a, V: 'a, K = i64> {
+    iter: FenwickIter<'a, V>,
+    offset: K,
+}
+
+impl<'a, V, K> ExtensibleFenwickIter<'a, V, K>
+where
+    V: Group,
+    K: PrimInt,
+{
+    /// Skip any keys whose value is zero.
+    pub fn skip_zeros(self) -> Self {
+        ExtensibleFenwickIter {
+            iter: self.iter.skip_zeros(),
+            offset: self.offset,
+        }
+    }
+
+    /// Yield cumulative values rather than single values.
+    pub fn cumulative(self) -> Cumulative<Self, V> {
+        let (front, back) = self.iter.bounds();
+        Cumulative::new(self, front, back)
+    }
+}
+
+impl<'a, V, K> Iterator for ExtensibleFenwickIter<'a, V, K>
+where
+    V: Group,
+    K: PrimInt,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let offset = self.offset;
+        self.iter.next().map(|(i, v)| (key_offset(offset, i).unwrap(), v))
+    }
+}
+
+impl<'a, V, K> DoubleEndedIterator for ExtensibleFenwickIter<'a, V, K>
+where
+    V: Group,
+    K: PrimInt,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let offset = self.offset;
+        self.iter.next_back().map(|(i, v)| (key_offset(offset, i).unwrap(), v))
+    }
+}
+
+/*****************************************************************************
+ * Serialization, as the tree's extent plus its non-zero entries
+ ****************************************************************************/
+
+#[cfg(feature = "serde")]
+mod serialize {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(rename = "FenwickTree")]
+    struct FenwickRepr<V, K> {
+        capacity: usize,
+        entries: Vec<(K, V)>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(rename = "ExtensibleFenwickTree")]
+    struct ExtensibleRepr<V, K> {
+        offset: K,
+        capacity: usize,
+        entries: Vec<(K, V)>,
+    }
+
+    /// Lay out `(key, value)` entries as a vector of single values, checking
+    /// that the keys are strictly increasing and within the `capacity` keys
+    /// starting from `offset`.
+    fn layout<V, K, E>(offset: K, capacity: usize, entries: Vec<(K, V)>) -> Result<Vec<V>, E>
+    where
+        V: Group,
+        K: PrimInt + fmt::Display,
+        E: Error,
+    {
+        if !extent_fits(offset, capacity) {
+            return Err(E::custom("extent does not fit in the key type"));
+        }
+        // The capacity comes from untrusted input, so report a failure to
+        // allocate it as an error rather than aborting.
+        let mut data = Vec::new();
+        if data.try_reserve_exact(capacity).is_err() {
+            return Err(E::custom(format_args!("cannot allocate a capacity of {}", capacity)));
+        }
+        data.resize(capacity, V::identity());
+        let mut last = None;
+        for (k, v) in entries {
+            if last >= Some(k) {
+                return Err(E::custom("keys are not in strictly increasing order"));
+            }
+            match key_position(k, offset) {
+                Some(i) if i < capacity => data[i] = v,
+                _ => {
+                    return Err(E::custom(format_args!(
+                        "key {} is outside the {} keys from {}", k, capacity, offset)));
+                }
+            }
+            last = Some(k);
+        }
+        Ok(data)
+    }
+
+    impl<V, K> Serialize for FenwickTree<V, K>
+    where
+        V: Group + Serialize,
+        K: PrimInt + Serialize,
+    {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            FenwickRepr {
+                capacity: self.capacity,
+                entries: self.iter().skip_zeros().collect(),
+            }.serialize(serializer)
+        }
+    }
+
+    impl<'de, V, K> Deserialize<'de> for FenwickTree<V, K>
+    where
+        V: Group + Deserialize<'de>,
+        K: PrimInt + fmt::Display + Deserialize<'de>,
+    {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let repr = FenwickRepr::deserialize(deserializer)?;
+            Ok(FenwickTree::from_singles(layout(K::zero(), repr.capacity, repr.entries)?))
+        }
+    }
+
+    impl<V, K> Serialize for ExtensibleFenwickTree<V, K>
+    where
+        V: Group + Serialize,
+        K: PrimInt + Serialize,
+    {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            ExtensibleRepr {
+                offset: self.offset,
+                capacity: self.tree.capacity,
+                entries: self.iter().skip_zeros().collect(),
+            }.serialize(serializer)
+        }
+    }
+
+    impl<'de, V, K> Deserialize<'de> for ExtensibleFenwickTree<V, K>
+    where
+        V: Group + Deserialize<'de>,
+        K: PrimInt + fmt::Display + Deserialize<'de>,
+    {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let repr = ExtensibleRepr::deserialize(deserializer)?;
+            Ok(ExtensibleFenwickTree {
+                offset: repr.offset,
+                tree: FenwickTree::from_singles(layout(repr.offset, repr.capacity, repr.entries)?),
+            })
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use std::ops::RangeBounds;
+
+use algebra::*;
+use bix::*;
+
+/*****************************************************************************
+ * Binary Index Tree over a grid of any number of dimensions
+ *****************************************************************************/
+
+/// A Fenwick tree over a `D`-dimensional grid of keys, each key an array of
+/// `D` coordinates. `get_cuml` sums the box of every key whose coordinates
+/// are all no greater than those given, and `get_range` sums any box, each
+/// in `O(log^D n)` time (times `2^D` for boxes not anchored at the origin).
+///
+/// Each dimension uses the same layout as a `FenwickTree`, so `insert`
+/// updates every combination of the nodes covering each coordinate, and
+/// `get_cuml` sums every combination of the nodes summing to each
+/// coordinate. The grid is allocated in advance, and its dimensions are
+/// fixed at creation time.
+///
+/// Since a cumulative value here covers a box of keys rather than every key
+/// before some other in a single order, this does not implement `CumlMap`,
+/// although its methods follow the same pattern.
+///
+/// # Examples
+/// ```
+/// use cuml_map::FenwickTreeND;
+///
+/// let mut cube: FenwickTreeND<u32, 3> = FenwickTreeND::with_capacity([4, 4, 4]);
+/// cube.insert([1, 2, 3], 5);
+/// cube.insert([3, 0, 1], 2);
+/// assert_eq!(cube.get_cuml([3, 3, 3]), 7);
+/// assert_eq!(cube.get_cuml([3, 1, 3]), 2);
+/// assert_eq!(cube.get_range([1..2, 0..4, 2..4]), 5);
+/// ```
+#[derive(Clone)]
+pub struct FenwickTreeND<V, const D: usize> {
+    dims: [usize; D],
+    data: Vec<V>,
+}
+
+impl<V, const D: usize> FenwickTreeND<V, D>
+where
+    V: Group,
+{
+    /// Create a `FenwickTreeND` object with the given size in each
+    /// dimension.
+    ///
+    /// # Panics
+    /// Panics if the total number of keys would overflow a `usize`.
+    pub fn with_capacity(dims: [usize; D]) -> Self {
+        let len = dims
+            .iter()
+            .try_fold(1usize, |n, &d| n.checked_mul(d))
+            .expect("Trying to create a FenwickTreeND with too many keys");
+        FenwickTreeND {
+            dims,
+            data: vec![V::identity(); len],
+        }
+    }
+
+    /// Get the size of the grid in each dimension.
+    pub fn capacity(&self) -> [usize; D] {
+        self.dims
+    }
+
+    /// Add `val` to the value at key `key`.
+    ///
+    /// # Panics
+    /// Panics if any coordinate of `key` is beyond the grid.
+    pub fn insert(&mut self, key: [usize; D], val: V) {
+        self.update(&key, &|x| x.combine(&val));
+    }
+
+    /// Set the value at key `key`, replacing any value previously there.
+    ///
+    /// # Panics
+    /// Panics if any coordinate of `key` is beyond the grid.
+    pub fn set(&mut self, key: [usize; D], val: V) {
+        let old = self.get_single(key);
+        self.update(&key, &|x| x.combine_inverse(&old).combine(&val));
+    }
+
+    /// Remove the value at key `key`, returning the value that was there.
+    pub fn remove(&mut self, key: [usize; D]) -> V {
+        if !self.contains(&key) {
+            return V::identity();
+        }
+        let old = self.get_single(key);
+        self.update(&key, &|x| x.combine_inverse(&old));
+        old
+    }
+
+    /// Get the sum of the values at every key whose coordinates are each no
+    /// greater than those of `key`. Coordinates beyond the grid are treated
+    /// as the last in their dimension.
+    pub fn get_cuml(&self, key: [usize; D]) -> V {
+        if self.data.is_empty() {
+            return V::identity();
+        }
+        let mut key = key;
+        for (k, &d) in key.iter_mut().zip(&self.dims) {
+            *k = (*k).min(d - 1);
+        }
+        self.prefix(0, 0, &key)
+    }
+
+    /// Get the value at key `key` alone.
+    pub fn get_single(&self, key: [usize; D]) -> V {
+        if !self.contains(&key) {
+            return V::identity();
+        }
+        self.get_range(key.map(|k| k..=k))
+    }
+
+    /// Get the sum of the values at every key whose coordinate in each
+    /// dimension lies within the corresponding range, by inclusion and
+    /// exclusion of the cumulative values at the box's corners.
+    pub fn get_range<R>(&self, ranges: [R; D]) -> V
+    where
+        R: RangeBounds<usize>,
+    {
+        let mut lo = [0; D];
+        let mut hi = [0; D];
+        for (d, r) in ranges.iter().enumerate() {
+            let bounds = (r.start_bound().cloned(), r.end_bound().cloned());
+            let (l, h) = clamp_range(bounds, 0, self.dims[d]);
+            if l >= h {
+                return V::identity();
+            }
+            lo[d] = l;
+            hi[d] = h;
+        }
+
+        // Each corner takes either the last key of the box, or the key
+        // before its first, in each dimension, and is added or subtracted
+        // according to how many of the latter it takes.
+        let mut add = V::identity();
+        let mut sub = V::identity();
+        'corners: for mask in 0..1usize << D {
+            let mut corner = hi.map(|h| h - 1);
+            for d in (0..D).filter(|d| mask & 1 << d != 0) {
+                if lo[d] == 0 {
+                    continue 'corners;
+                }
+                corner[d] = lo[d] - 1;
+            }
+            let sum = self.prefix(0, 0, &corner);
+            if mask.count_ones() % 2 == 0 {
+                add = add.combine(&sum);
+            } else {
+                sub = sub.combine(&sum);
+            }
+        }
+        add.combine_inverse(&sub)
+    }
+
+    /// Whether every coordinate of `key` lies within the grid.
+    fn contains(&self, key: &[usize; D]) -> bool {
+        key.iter().zip(&self.dims).all(|(&k, &d)| k < d)
+    }
+
+    /// Apply `f` to every node covering `key`.
+    fn update<F>(&mut self, key: &[usize; D], f: &F)
+    where
+        F: Fn(&V) -> V,
+    {
+        assert!(self.contains(key), "Trying to update a key beyond the grid");
+        self.update_from(0, 0, key, f);
+    }
+
+    /// Apply `f` to every node covering `key`, among the nodes whose first
+    /// `dim` coordinates give the row-major position `at`.
+    fn update_from<F>(&mut self, dim: usize, at: usize, key: &[usize; D], f: &F)
+    where
+        F: Fn(&V) -> V,
+    {
+        if dim == D {
+            self.data[at] = f(&self.data[at]);
+            return;
+        }
+        for i in covering_nodes(self.dims[dim], key[dim]) {
+            self.update_from(dim + 1, at * self.dims[dim] + i, key, f);
+        }
+    }
+
+    /// Sum the nodes making up the cumulative value at `key`, which must be
+    /// within the grid, among the nodes whose first `dim` coordinates give
+    /// the row-major position `at`.
+    fn prefix(&self, dim: usize, at: usize, key: &[usize; D]) -> V {
+        if dim == D {
+            return self.data[at].clone();
+        }
+        prefix_nodes(key[dim]).fold(V::identity(), |sum, i| {
+            sum.combine(&self.prefix(dim + 1, at * self.dims[dim] + i, key))
+        })
+    }
+}
+
+impl<V: Group, const D: usize> Default for FenwickTreeND<V, D> {
+    /// Create a `FenwickTreeND` with no keys.
+    fn default() -> Self {
+        Self::with_capacity([0; D])
+    }
+}
+
+/*****************************************************************************
+ * Binary Index Tree over a two-dimensional grid
+ *****************************************************************************/
+
+/// A Fenwick tree over a two-dimensional grid of keys `(x, y)`, such as a
+/// heatmap. This is a `FenwickTreeND` of two dimensions, taking its keys as
+/// pairs rather than arrays.
+///
+/// # Examples
+/// ```
+/// use cuml_map::FenwickTree2D;
+///
+/// // Requests by latency bucket and minute.
+/// let mut heat: FenwickTree2D<u64> = FenwickTree2D::with_capacity(8, 60);
+/// heat.insert((2, 10), 4);
+/// heat.insert((5, 12), 1);
+/// heat.insert((6, 40), 3);
+/// assert_eq!(heat.get_cuml((5, 30)), 5);
+/// assert_eq!(heat.get_range(4.., 10..20), 1);
+/// ```
+#[derive(Clone)]
+pub struct FenwickTree2D<V> {
+    tree: FenwickTreeND<V, 2>,
+}
+
+impl<V> FenwickTree2D<V>
+where
+    V: Group,
+{
+    /// Create a `FenwickTree2D` object covering the keys `(x, y)` with `x`
+    /// below `width` and `y` below `height`.
+    pub fn with_capacity(width: usize, height: usize) -> Self {
+        FenwickTree2D {
+            tree: FenwickTreeND::with_capacity([width, height]),
+        }
+    }
+
+    /// Get the width and height of the grid.
+    pub fn capacity(&self) -> (usize, usize) {
+        let [w, h] = self.tree.capacity();
+        (w, h)
+    }
+
+    /// Add `val` to the value at key `(x, y)`.
+    ///
+    /// # Panics
+    /// Panics if `(x, y)` is beyond the grid.
+    pub fn insert(&mut self, (x, y): (usize, usize), val: V) {
+        self.tree.insert([x, y], val);
+    }
+
+    /// Set the value at key `(x, y)`, replacing any value previously there.
+    ///
+    /// # Panics
+    /// Panics if `(x, y)` is beyond the grid.
+    pub fn set(&mut self, (x, y): (usize, usize), val: V) {
+        self.tree.set([x, y], val);
+    }
+
+    /// Remove the value at key `(x, y)`, returning the value that was there.
+    pub fn remove(&mut self, (x, y): (usize, usize)) -> V {
+        self.tree.remove([x, y])
+    }
+
+    /// Get the sum of the values over the rectangle `[0, x] x [0, y]`.
+    pub fn get_cuml(&self, (x, y): (usize, usize)) -> V {
+        self.tree.get_cuml([x, y])
+    }
+
+    /// Get the value at key `(x, y)` alone.
+    pub fn get_single(&self, (x, y): (usize, usize)) -> V {
+        self.tree.get_single([x, y])
+    }
+
+    /// Get the sum of the values over the rectangle of keys whose `x` lies
+    /// within `xs` and whose `y` lies within `ys`.
+    pub fn get_range<R, S>(&self, xs: R, ys: S) -> V
+    where
+        R: RangeBounds<usize>,
+        S: RangeBounds<usize>,
+    {
+        let (w, h) = self.capacity();
+        let (x0, x1) = clamp_range(xs, 0, w);
+        let (y0, y1) = clamp_range(ys, 0, h);
+        self.tree.get_range([x0..x1, y0..y1])
+    }
+}
+
+impl<V: Group> Default for FenwickTree2D<V> {
+    /// Create a `FenwickTree2D` with no keys.
+    fn default() -> Self {
+        Self::with_capacity(0, 0)
+    }
+}
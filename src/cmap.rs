@@ -1,3 +1,10 @@
+use std::error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::RangeBounds;
+
+use algebra::*;
+
 /// Trait for building and querying mappings between keys and cumulative
 /// values.
 pub trait CumlMap {
@@ -8,20 +15,165 @@ pub trait CumlMap {
     type Value;
 
     /// Insert an entry into the mapping.
-    fn insert(&mut self, Self::Key, Self::Value);
+    fn insert(&mut self, key: Self::Key, val: Self::Value);
+
+    /// Set the value at the specified key, replacing (rather than adding
+    /// to) any value previously stored there.
+    fn set(&mut self, key: Self::Key, val: Self::Value);
+
+    /// Remove the entry at the specified key, returning the value that was
+    /// stored there (zero if there was no such entry).
+    fn remove(&mut self, key: Self::Key) -> Self::Value;
 
     /// Get the cumulative value up to and including
     /// the specified key.
-    fn get_cuml(&self, Self::Key) -> Self::Value;
+    fn get_cuml(&self, key: Self::Key) -> Self::Value;
 
     /// Get the value at the specified key (not the cumulative value).
-    fn get_single(&self, Self::Key) -> Self::Value;
+    fn get_single(&self, key: Self::Key) -> Self::Value;
+
+    /// Get the sum of the values at every key within the specified range.
+    /// Either end of the range may be inclusive, exclusive, or unbounded.
+    fn get_range<R>(&self, range: R) -> Self::Value
+    where
+        R: RangeBounds<Self::Key>;
 
     /// Get the first key at which the cumulative value equals or exceeds
     /// the specified value, if such a key exists.
     /// Note that if the result of this function is only defined if the
     /// cumulative value is non-decreasing. If you start putting negative
     /// values into your mappings, you will get strange results from this
-    /// function.
-    fn get_quantile(&self, Self::Value) -> Option<Self::Key>;
+    /// function: the key returned will be one at which the cumulative value
+    /// crosses from below `quant` to at least `quant`, but not necessarily
+    /// the first such key.
+    ///
+    /// Values need only be partially ordered. If `quant` is not comparable
+    /// even with itself (a floating-point NaN) then the result is `None`.
+    /// Any stored value which is not comparable with `quant`, such as a NaN
+    /// among the values of the mapping, is treated as though it were at
+    /// least `quant`, so the search will stop at or before the first key
+    /// whose cumulative value is NaN.
+    fn get_quantile(&self, quant: Self::Value) -> Option<Self::Key>
+    where
+        Self::Value: PartialOrd;
+}
+
+/// The ways in which the fallible methods of a mapping can fail, rather than
+/// panicking or silently overflowing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// A key lies beyond the keys the mapping can hold.
+    KeyOutOfRange,
+    /// The mapping can hold no keys at all.
+    ZeroCapacity,
+    /// A sum of values overflowed the value type.
+    Overflow,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::KeyOutOfRange => write!(f, "key is beyond the capacity of the mapping"),
+            Error::ZeroCapacity => write!(f, "mapping has zero capacity"),
+            Error::Overflow => write!(f, "arithmetic overflow in mapping"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// Fallible versions of the `CumlMap` methods, for mappings of integer values,
+/// which use checked arithmetic to report overflow as an `Error` rather than
+/// panicking (in debug builds) or wrapping around (in release builds). They
+/// also report keys which the mapping cannot hold, where the plain methods
+/// would panic.
+///
+/// An update which fails leaves the mapping unchanged. Updates check only the
+/// partial sums which they change, so a cumulative value may still overflow
+/// after a successful update; the query which computes it will then return
+/// `Error::Overflow`.
+pub trait CheckedCumlMap: CumlMap {
+    /// Insert an entry into the mapping, as for `CumlMap::insert`.
+    fn checked_insert(&mut self, key: Self::Key, val: Self::Value) -> Result<(), Error>;
+
+    /// Set the value at the specified key, as for `CumlMap::set`.
+    fn checked_set(&mut self, key: Self::Key, val: Self::Value) -> Result<(), Error>;
+
+    /// Remove the entry at the specified key, as for `CumlMap::remove`.
+    fn checked_remove(&mut self, key: Self::Key) -> Result<Self::Value, Error>;
+
+    /// Get the cumulative value up to and including the specified key, as
+    /// for `CumlMap::get_cuml`.
+    fn checked_get_cuml(&self, key: Self::Key) -> Result<Self::Value, Error>;
+
+    /// Get the value at the specified key, as for `CumlMap::get_single`.
+    fn checked_get_single(&self, key: Self::Key) -> Result<Self::Value, Error>;
+
+    /// Get the sum of the values at every key within the specified range, as
+    /// for `CumlMap::get_range`.
+    fn checked_get_range<R>(&self, range: R) -> Result<Self::Value, Error>
+    where
+        R: RangeBounds<Self::Key>;
+}
+
+/// Hash a mapping's non-zero `(key, value)` entries, followed by their number
+/// so that no sequence of entries hashes as a prefix of another.
+pub(crate) fn hash_entries<I, H>(entries: I, state: &mut H)
+where
+    I: Iterator,
+    I::Item: Hash,
+    H: Hasher,
+{
+    let mut n = 0usize;
+    for e in entries {
+        e.hash(state);
+        n += 1;
+    }
+    n.hash(state);
+}
+
+/// An iterator over `(key, cumulative value)` pairs, in key order, built on
+/// top of an iterator over the `(key, value)` pairs of a mapping.
+///
+/// This is returned by the `cumulative` method of each mapping's iterator
+/// type; see, for example, `FenwickIter::cumulative`.
+pub struct Cumulative<I, V> {
+    iter: I,
+    front: V,
+    back: V,
+}
+
+impl<I, V> Cumulative<I, V> {
+    /// Wrap `iter`, given the cumulative value of every key before the
+    /// first one `iter` will yield, and up to and including the last one.
+    pub(crate) fn new(iter: I, front: V, back: V) -> Self {
+        Cumulative { iter, front, back }
+    }
+}
+
+impl<I, K, V> Iterator for Cumulative<I, V>
+where
+    I: Iterator<Item = (K, V)>,
+    V: Group,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (k, v) = self.iter.next()?;
+        self.front = self.front.combine(&v);
+        Some((k, self.front.clone()))
+    }
+}
+
+impl<I, K, V> DoubleEndedIterator for Cumulative<I, V>
+where
+    I: DoubleEndedIterator<Item = (K, V)>,
+    V: Group,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let (k, v) = self.iter.next_back()?;
+        let c = self.back.clone();
+        self.back = self.back.combine_inverse(&v);
+        Some((k, c))
+    }
 }
@@ -0,0 +1,343 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::RangeBounds;
+
+use algebra::*;
+use bix::*;
+use cmap::*;
+use rctree::{before_end, before_start};
+
+/*****************************************************************************
+ * Binary Index Tree over a fixed universe of sparse keys
+ *****************************************************************************/
+
+/// A `FenwickTree` over a universe of keys fixed at creation time, which may
+/// be of any ordered type and as sparse as you like. Each key is mapped to
+/// its position in the sorted universe by binary search, so the tree takes
+/// space only for the keys in the universe, and each operation takes
+/// `O(log n)` time, with none of the rebalancing of a `CumlTree`.
+///
+/// This suits offline work where every key is known in advance, such as
+/// user IDs. Inserting a key outside the universe panics; every query
+/// accepts any key, and treats those outside the universe as holding zero.
+///
+/// # Examples
+/// ```
+/// use cuml_map::{CompressedFenwickTree, CumlMap};
+///
+/// let users = vec![90210, 12, 4_000_000_007, 777];
+/// let mut spend: CompressedFenwickTree<u64, u32> = CompressedFenwickTree::new(users);
+/// spend.insert(777, 5);
+/// spend.insert(4_000_000_007, 2);
+/// spend.insert(12, 1);
+/// assert_eq!(spend.get_cuml(1000), 6);
+/// assert_eq!(spend.get_range(13..), 7);
+/// assert_eq!(spend.get_quantile(7), Some(4_000_000_007));
+/// ```
+#[derive(Clone)]
+pub struct CompressedFenwickTree<K, V> {
+    keys: Vec<K>,
+    tree: FenwickTree<V>,
+}
+
+impl<K, V> CompressedFenwickTree<K, V>
+where
+    K: Ord,
+    V: Group,
+{
+    /// Create a `CompressedFenwickTree` object covering the given keys, in
+    /// any order. Repeated keys are covered once.
+    pub fn new<I>(keys: I) -> Self
+    where
+        I: IntoIterator<Item = K>,
+    {
+        let mut keys: Vec<K> = keys.into_iter().collect();
+        keys.sort_unstable();
+        keys.dedup();
+        Self::from_sorted_keys(keys)
+    }
+
+    /// Create a `CompressedFenwickTree` object covering the given keys,
+    /// which must already be sorted, without sorting them again.
+    ///
+    /// # Panics
+    /// Panics if the keys are not in strictly increasing order.
+    ///
+    /// # Examples
+    /// ```
+    /// use cuml_map::{CompressedFenwickTree, CumlMap};
+    ///
+    /// let words = vec!["ant", "bee", "cat"];
+    /// let mut ct: CompressedFenwickTree<&str, i32> = CompressedFenwickTree::from_sorted_keys(words);
+    /// ct.insert("bee", 3);
+    /// assert_eq!(ct.get_cuml("bat"), 0);
+    /// assert_eq!(ct.get_cuml("cow"), 3);
+    /// ```
+    pub fn from_sorted_keys(keys: Vec<K>) -> Self {
+        assert!(
+            keys.windows(2).all(|w| w[0] < w[1]),
+            "keys passed to from_sorted_keys are out of order"
+        );
+        CompressedFenwickTree {
+            tree: FenwickTree::with_capacity(keys.len()),
+            keys,
+        }
+    }
+
+    /// Get the universe of keys covered by the tree, in order.
+    pub fn keys(&self) -> &[K] {
+        &self.keys
+    }
+
+    /// Get the number of keys covered by the tree.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Check whether the tree covers no keys at all.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Get the position of `key` in the universe, if it is there.
+    fn position(&self, key: &K) -> Option<usize> {
+        self.keys.binary_search(key).ok()
+    }
+
+    /// Get the position of `key` in the universe, panicking if it is not
+    /// there.
+    fn index(&self, key: &K) -> usize {
+        self.position(key).expect("Trying to update a key outside the tree's universe")
+    }
+
+    /// Get the half-open range of positions of the keys within `range`.
+    fn positions<R>(&self, range: &R) -> (usize, usize)
+    where
+        R: RangeBounds<K>,
+    {
+        let lo = self.keys.partition_point(|k| before_start(range.start_bound(), k));
+        let hi = self.keys.partition_point(|k| before_end(range.end_bound(), k));
+        (lo, hi.max(lo))
+    }
+
+    /// Get the first key at which the cumulative value equals or exceeds
+    /// the specified value, if such a key exists. This is the same as
+    /// `CumlMap::get_quantile`, but returns a reference to the key rather
+    /// than a copy of it.
+    pub fn get_quantile_ref(&self, quant: V) -> Option<&K>
+    where
+        V: PartialOrd,
+    {
+        self.tree.get_quantile(quant).map(|i| &self.keys[i])
+    }
+
+    /// Iterate over every `(key, value)` pair in the tree's universe, in key
+    /// order.
+    ///
+    /// # Examples
+    /// ```
+    /// use cuml_map::{CompressedFenwickTree, CumlMap};
+    ///
+    /// let mut ct: CompressedFenwickTree<i32, u32> = CompressedFenwickTree::new(vec![-8, 0, 5]);
+    /// ct.insert(5, 2);
+    /// ct.insert(-8, 1);
+    /// let v: Vec<_> = ct.iter().collect();
+    /// assert_eq!(v, vec![(&-8, 1), (&0, 0), (&5, 2)]);
+    /// let v: Vec<_> = ct.iter().skip_zeros().cumulative().collect();
+    /// assert_eq!(v, vec![(&-8, 1), (&5, 3)]);
+    /// ```
+    pub fn iter(&self) -> CompressedFenwickIter<'_, K, V> {
+        self.range(..)
+    }
+
+    /// Iterate over every `(key, cumulative value)` pair in the tree's
+    /// universe, in key order.
+    pub fn cuml_iter(&self) -> Cumulative<CompressedFenwickIter<'_, K, V>, V> {
+        self.iter().cumulative()
+    }
+
+    /// Iterate over the `(key, value)` pairs in the tree's universe whose
+    /// keys lie within the given range, in key order.
+    pub fn range<R>(&self, range: R) -> CompressedFenwickIter<'_, K, V>
+    where
+        R: RangeBounds<K>,
+    {
+        let (lo, hi) = self.positions(&range);
+        CompressedFenwickIter {
+            keys: &self.keys,
+            iter: self.tree.range(lo..hi),
+        }
+    }
+
+    /// Add the value at every key of `other` to the value at the same key in
+    /// this tree. If both trees cover the same universe this takes time
+    /// linear in its size; otherwise each non-zero entry of `other` is
+    /// combined with the value at its key in turn.
+    ///
+    /// # Panics
+    /// Panics if `other` has a non-zero value at a key outside this tree's
+    /// universe.
+    pub fn merge_from(&mut self, other: &Self) {
+        self.merge_with(other, V::combine);
+    }
+
+    /// Subtract the value at every key of `other` from the value at the same
+    /// key in this tree, in the same way as `merge_from`.
+    ///
+    /// # Panics
+    /// Panics if `other` has a non-zero value at a key outside this tree's
+    /// universe.
+    pub fn subtract(&mut self, other: &Self) {
+        self.merge_with(other, V::combine_inverse);
+    }
+
+    /// Replace the value `x` at every key with `f(x, y)`, where `y` is the
+    /// value at the same key of `other`, as for `FenwickTree::merge_from`.
+    fn merge_with<F>(&mut self, other: &Self, f: F)
+    where
+        F: Fn(&V, &V) -> V,
+    {
+        if self.keys == other.keys {
+            self.tree.merge_with(&other.tree, f);
+            return;
+        }
+        for (k, v) in other.iter().skip_zeros() {
+            let i = self.index(k);
+            let old = self.tree.get_single(i);
+            self.tree.set(i, f(&old, &v));
+        }
+    }
+}
+
+impl<K, V> CumlMap for CompressedFenwickTree<K, V>
+where
+    K: Ord + Clone,
+    V: Group,
+{
+    type Key = K;
+    type Value = V;
+
+    fn insert(&mut self, key: Self::Key, val: Self::Value) {
+        let i = self.index(&key);
+        self.tree.insert(i, val);
+    }
+
+    fn set(&mut self, key: Self::Key, val: Self::Value) {
+        let i = self.index(&key);
+        self.tree.set(i, val);
+    }
+
+    fn remove(&mut self, key: Self::Key) -> Self::Value {
+        match self.position(&key) {
+            None => V::identity(),
+            Some(i) => self.tree.remove(i),
+        }
+    }
+
+    fn get_cuml(&self, key: Self::Key) -> Self::Value {
+        match self.keys.partition_point(|k| k <= &key) {
+            0 => V::identity(),
+            n => self.tree.get_cuml(n - 1),
+        }
+    }
+
+    fn get_single(&self, key: Self::Key) -> Self::Value {
+        match self.position(&key) {
+            None => V::identity(),
+            Some(i) => self.tree.get_single(i),
+        }
+    }
+
+    fn get_range<R>(&self, range: R) -> Self::Value
+    where
+        R: RangeBounds<Self::Key>,
+    {
+        let (lo, hi) = self.positions(&range);
+        self.tree.get_range(lo..hi)
+    }
+
+    fn get_quantile(&self, quant: Self::Value) -> Option<Self::Key>
+    where
+        V: PartialOrd,
+    {
+        self.get_quantile_ref(quant).cloned()
+    }
+}
+
+impl<K: Ord, V: Group> Default for CompressedFenwickTree<K, V> {
+    /// Create a `CompressedFenwickTree` covering no keys.
+    fn default() -> Self {
+        Self::from_sorted_keys(Vec::new())
+    }
+}
+
+impl<K, V> fmt::Debug for CompressedFenwickTree<K, V>
+where
+    K: Ord + fmt::Debug,
+    V: Group + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter().skip_zeros()).finish()
+    }
+}
+
+impl<K: Ord, V: Group> PartialEq for CompressedFenwickTree<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().skip_zeros().eq(other.iter().skip_zeros())
+    }
+}
+
+impl<K: Ord, V: Group + Eq> Eq for CompressedFenwickTree<K, V> {}
+
+impl<K: Ord + Hash, V: Group + Hash> Hash for CompressedFenwickTree<K, V> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_entries(self.iter().skip_zeros(), state)
+    }
+}
+
+/// An iterator over the `(key, value)` pairs of a `CompressedFenwickTree`.
+pub struct CompressedFenwickIter<'a, K: 'a, V: 'a> {
+    keys: &'a [K],
+    iter: FenwickIter<'a, V>,
+}
+
+impl<'a, K, V> CompressedFenwickIter<'a, K, V>
+where
+    V: Group,
+{
+    /// Skip any keys whose value is zero.
+    pub fn skip_zeros(self) -> Self {
+        CompressedFenwickIter {
+            keys: self.keys,
+            iter: self.iter.skip_zeros(),
+        }
+    }
+
+    /// Yield cumulative values rather than single values.
+    pub fn cumulative(self) -> Cumulative<Self, V> {
+        let (front, back) = self.iter.bounds();
+        Cumulative::new(self, front, back)
+    }
+}
+
+impl<'a, K, V> Iterator for CompressedFenwickIter<'a, K, V>
+where
+    V: Group,
+{
+    type Item = (&'a K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let keys = self.keys;
+        self.iter.next().map(|(i, v)| (&keys[i], v))
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for CompressedFenwickIter<'a, K, V>
+where
+    V: Group,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let keys = self.keys;
+        self.iter.next_back().map(|(i, v)| (&keys[i], v))
+    }
+}
@@ -0,0 +1,458 @@
+use std::marker::PhantomData;
+use std::num::Wrapping;
+use std::ops::RangeBounds;
+use std::sync::atomic::Ordering;
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::sync::atomic::{AtomicI32, AtomicI64, AtomicIsize, AtomicU32, AtomicU64, AtomicUsize};
+
+use algebra::*;
+use bix::*;
+use cmap::*;
+
+/*****************************************************************************
+ * Binary Index Tree over atomic integers, for concurrent updates
+ *****************************************************************************/
+
+/// An integer type with a matching atomic type, which may be stored in a
+/// `ConcurrentFenwickTree`.
+pub trait AtomicValue: Group + Copy {
+    /// The atomic version of this type.
+    type Atomic: Send + Sync;
+
+    /// Create an atomic holding `v`.
+    fn new_atomic(v: Self) -> Self::Atomic;
+
+    /// Read the value held by `a`.
+    fn load(a: &Self::Atomic) -> Self;
+
+    /// Add `v` to the value held by `a`, wrapping around on overflow.
+    fn fetch_add(a: &Self::Atomic, v: Self);
+
+    /// Subtract `v` from the value held by `a`, wrapping around on overflow.
+    fn fetch_sub(a: &Self::Atomic, v: Self);
+}
+
+macro_rules! impl_atomic_value {
+    ($($t:ty => $a:ty),*) => {
+        $(
+            impl AtomicValue for $t {
+                type Atomic = $a;
+
+                fn new_atomic(v: Self) -> Self::Atomic {
+                    <$a>::new(v)
+                }
+
+                fn load(a: &Self::Atomic) -> Self {
+                    a.load(Ordering::Relaxed)
+                }
+
+                fn fetch_add(a: &Self::Atomic, v: Self) {
+                    a.fetch_add(v, Ordering::Relaxed);
+                }
+
+                fn fetch_sub(a: &Self::Atomic, v: Self) {
+                    a.fetch_sub(v, Ordering::Relaxed);
+                }
+            }
+        )*
+    };
+}
+
+impl_atomic_value!(i32 => AtomicI32, i64 => AtomicI64, isize => AtomicIsize);
+impl_atomic_value!(u32 => AtomicU32, u64 => AtomicU64, usize => AtomicUsize);
+
+/// A `FenwickTree` whose nodes are atomic integers, so that any number of
+/// threads may `insert` into a shared tree at once without locking. Each
+/// insertion atomically adds its value to each of the `O(log n)` nodes
+/// covering its key, in turn. Unlike in the other maps, values wrap around
+/// on overflow rather than panicking.
+///
+/// # Consistency
+/// Queries read each node atomically, but do not read all of them at one
+/// moment, so while insertions are running:
+///
+/// * `get_cuml` counts each insertion either in full or not at all, since
+///   exactly one of the nodes it reads covers any given key. Every insertion
+///   which happened before the query began (for example, on a thread which
+///   has since been joined) is counted, but of those running at the same
+///   time, a later one may be counted where an earlier one is not. So if
+///   every value inserted is non-negative, the result lies between the true
+///   cumulative values at the start and at the end of the query, and a
+///   thread repeating a query never sees its result decrease.
+/// * `get_single`, `get_range` and `get_quantile` read nodes which overlap,
+///   so they may count only part of a concurrent insertion, and may give
+///   results which no sequence of insertions could have produced, wrapping
+///   around where these fall outside the value type.
+///
+/// Once all insertions have finished, every query is exact.
+///
+/// # Examples
+/// ```
+/// use cuml_map::{ConcurrentFenwickTree, CumlMap};
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let ft: Arc<ConcurrentFenwickTree<u64>> = Arc::new(ConcurrentFenwickTree::with_capacity(8));
+/// let threads: Vec<_> = (0..4)
+///     .map(|i| {
+///         let ft = Arc::clone(&ft);
+///         thread::spawn(move || ft.insert(i, 10))
+///     })
+///     .collect();
+/// for t in threads {
+///     t.join().unwrap();
+/// }
+/// assert_eq!(ft.get_cuml(2), 30);
+/// ```
+pub struct ConcurrentFenwickTree<V: AtomicValue> {
+    capacity: usize,
+    data: Vec<V::Atomic>,
+}
+
+impl<V> ConcurrentFenwickTree<V>
+where
+    V: AtomicValue,
+{
+    /// Create a `ConcurrentFenwickTree` object with a fixed capacity.
+    pub fn with_capacity(c: usize) -> Self {
+        ConcurrentFenwickTree {
+            capacity: c,
+            data: (0..c).map(|_| V::new_atomic(V::identity())).collect(),
+        }
+    }
+
+    /// Get the number of keys covered by the tree.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Add `val` to the value at key `key`. This may be called from many
+    /// threads at once.
+    ///
+    /// # Panics
+    /// Panics if `key` is beyond the tree's capacity.
+    pub fn insert(&self, key: usize, val: V) {
+        self.update(key, |a| V::fetch_add(a, val));
+    }
+
+    /// Copy the tree's current contents into a `FenwickTree`. If insertions
+    /// are running, the copy is subject to the same caveats as `get_range`.
+    pub fn to_tree(&self) -> FenwickTree<V> {
+        FenwickTree {
+            capacity: self.capacity,
+            data: self.data.iter().map(V::load).collect(),
+            keys: PhantomData,
+        }
+    }
+
+    /// Add the value at every key of `other` to the value at the same key in
+    /// this tree, as for `FenwickTree::merge_from`.
+    ///
+    /// # Panics
+    /// Panics if `other` has a greater capacity than this tree.
+    pub fn merge_from(&mut self, other: &Self) {
+        let mut tree = self.to_tree();
+        tree.merge_from(&other.to_tree());
+        *self = tree.into();
+    }
+
+    /// Subtract the value at every key of `other` from the value at the same
+    /// key in this tree, as for `FenwickTree::subtract`.
+    ///
+    /// # Panics
+    /// Panics if `other` has a greater capacity than this tree.
+    pub fn subtract(&mut self, other: &Self) {
+        let mut tree = self.to_tree();
+        tree.subtract(&other.to_tree());
+        *self = tree.into();
+    }
+
+    /// Apply `f` to every node in the tree that covers `key`.
+    fn update<F>(&self, key: usize, f: F)
+    where
+        F: Fn(&V::Atomic),
+    {
+        assert!(key < self.capacity);
+        for i in covering_nodes(self.capacity, key) {
+            f(&self.data[i]);
+        }
+    }
+
+    /// Read the node at position `i`. Queries combine nodes read at
+    /// different moments, which may not be consistent with one another, so
+    /// they work over `Wrapping` values rather than panicking on overflow.
+    fn node(&self, i: usize) -> Wrapping<V> {
+        Wrapping(V::load(&self.data[i]))
+    }
+}
+
+impl<V> From<FenwickTree<V>> for ConcurrentFenwickTree<V>
+where
+    V: AtomicValue,
+{
+    fn from(tree: FenwickTree<V>) -> Self {
+        ConcurrentFenwickTree {
+            capacity: tree.capacity,
+            data: tree.data.into_iter().map(V::new_atomic).collect(),
+        }
+    }
+}
+
+impl<V> CumlMap for ConcurrentFenwickTree<V>
+where
+    V: AtomicValue,
+    Wrapping<V>: Group,
+{
+    type Key = usize;
+    type Value = V;
+
+    fn insert(&mut self, key: Self::Key, val: Self::Value) {
+        ConcurrentFenwickTree::insert(self, key, val);
+    }
+
+    fn set(&mut self, key: Self::Key, val: Self::Value) {
+        let old = self.get_single(key);
+        self.update(key, |a| {
+            V::fetch_add(a, val);
+            V::fetch_sub(a, old);
+        });
+    }
+
+    fn remove(&mut self, key: Self::Key) -> Self::Value {
+        if key >= self.capacity {
+            return V::identity();
+        }
+        let old = self.get_single(key);
+        self.update(key, |a| V::fetch_sub(a, old));
+        old
+    }
+
+    fn get_cuml(&self, key: Self::Key) -> Self::Value {
+        prefix_sum(self.capacity, |i| self.node(i), key).0
+    }
+
+    fn get_single(&self, key: Self::Key) -> Self::Value {
+        single_value(self.capacity, |i| self.node(i), key).0
+    }
+
+    fn get_range<R>(&self, range: R) -> Self::Value
+    where
+        R: RangeBounds<Self::Key>,
+    {
+        let (lo, hi) = clamp_range(range, 0, self.capacity);
+        range_sum(self.capacity, |i| self.node(i), lo, hi).0
+    }
+
+    fn get_quantile(&self, quant: Self::Value) -> Option<Self::Key>
+    where
+        V: PartialOrd,
+    {
+        quantile(self.capacity, |i| self.node(i), Wrapping(quant))
+    }
+}
+
+/*****************************************************************************
+ * Binary Index Tree split into per-thread shards, for concurrent updates
+ *****************************************************************************/
+
+/// The counter from which each thread takes the index of its shard.
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static SHARD: usize = NEXT_SHARD.fetch_add(1, Ordering::Relaxed);
+}
+
+/// A `FenwickTree` split into a number of shards of equal capacity, each
+/// behind its own lock, so that many threads may `insert` into a shared tree
+/// at once while rarely contending for the same lock. Each thread always
+/// inserts into the same shard, with threads spread over the shards in turn.
+///
+/// Unlike `ConcurrentFenwickTree`, this works for any `Group` of values, and
+/// every query sees a consistent state of the whole tree: queries lock all of
+/// the shards, and read each node of the tree as the sum of that node across
+/// every shard. Queries therefore cost a factor of the number of shards more
+/// than in a single `FenwickTree`, and briefly block insertions; `merged` can
+/// be used to collect the shards into a single tree for repeated querying.
+///
+/// # Examples
+/// ```
+/// use cuml_map::{CumlMap, ShardedFenwickTree};
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let ft: Arc<ShardedFenwickTree<f64>> = Arc::new(ShardedFenwickTree::new(8, 4));
+/// let threads: Vec<_> = (0..4)
+///     .map(|i| {
+///         let ft = Arc::clone(&ft);
+///         thread::spawn(move || ft.insert(i, 2.5))
+///     })
+///     .collect();
+/// for t in threads {
+///     t.join().unwrap();
+/// }
+/// assert_eq!(ft.get_cuml(2), 7.5);
+/// assert_eq!(ft.merged().get_cuml(3), 10.0);
+/// ```
+pub struct ShardedFenwickTree<V: Group> {
+    capacity: usize,
+    shards: Vec<Mutex<FenwickTree<V>>>,
+}
+
+impl<V> ShardedFenwickTree<V>
+where
+    V: Group,
+{
+    /// Create a `ShardedFenwickTree` object with a fixed capacity, split into
+    /// `shards` shards.
+    ///
+    /// # Panics
+    /// Panics if `shards` is zero.
+    pub fn new(capacity: usize, shards: usize) -> Self {
+        assert!(shards > 0, "A sharded tree needs at least one shard");
+        ShardedFenwickTree {
+            capacity,
+            shards: (0..shards)
+                .map(|_| Mutex::new(FenwickTree::with_capacity(capacity)))
+                .collect(),
+        }
+    }
+
+    /// Create a `ShardedFenwickTree` object with a fixed capacity, and one
+    /// shard for each thread the machine can run in parallel.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let shards = thread::available_parallelism().map_or(1, |n| n.get());
+        ShardedFenwickTree::new(capacity, shards)
+    }
+
+    /// Get the number of keys covered by the tree.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Get the number of shards the tree is split into.
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Add `val` to the value at key `key`, in the shard belonging to the
+    /// calling thread. This may be called from many threads at once.
+    ///
+    /// # Panics
+    /// Panics if `key` is beyond the tree's capacity, or if an earlier
+    /// insertion panicked part way through updating a shard.
+    pub fn insert(&self, key: usize, val: V) {
+        assert!(key < self.capacity);
+        let shard = SHARD.with(|s| *s) % self.shards.len();
+        self.shards[shard].lock().unwrap().insert(key, val);
+    }
+
+    /// Merge every shard into a single `FenwickTree`, in time linear in the
+    /// capacity and the number of shards.
+    pub fn merged(&self) -> FenwickTree<V> {
+        let shards = self.lock_all();
+        let mut tree = FenwickTree::with_capacity(self.capacity);
+        for shard in &shards {
+            tree.merge(shard);
+        }
+        tree
+    }
+
+    /// Add the value at every key of `other` to the value at the same key in
+    /// this tree, as for `FenwickTree::merge_from`.
+    ///
+    /// # Panics
+    /// Panics if `other` has a greater capacity than this tree.
+    pub fn merge_from(&mut self, other: &Self) {
+        let other = other.merged();
+        self.shards_mut().next().unwrap().merge_from(&other);
+    }
+
+    /// Subtract the value at every key of `other` from the value at the same
+    /// key in this tree, as for `FenwickTree::subtract`.
+    ///
+    /// # Panics
+    /// Panics if `other` has a greater capacity than this tree.
+    pub fn subtract(&mut self, other: &Self) {
+        let other = other.merged();
+        self.shards_mut().next().unwrap().subtract(&other);
+    }
+
+    /// Lock every shard, in order, so that inserting threads which each hold
+    /// only a single lock cannot deadlock with us.
+    fn lock_all(&self) -> Vec<MutexGuard<'_, FenwickTree<V>>> {
+        self.shards.iter().map(|s| s.lock().unwrap()).collect()
+    }
+
+    /// Get every shard, which needs no locking given exclusive access.
+    fn shards_mut(&mut self) -> impl Iterator<Item = &mut FenwickTree<V>> {
+        self.shards.iter_mut().map(|s| s.get_mut().unwrap())
+    }
+}
+
+/// Get node `i` of the tree formed by summing every shard.
+fn node<V: Group>(shards: &[MutexGuard<FenwickTree<V>>], i: usize) -> V {
+    shards.iter().fold(V::identity(), |acc, s| acc.combine(&s.data[i]))
+}
+
+impl<V> From<FenwickTree<V>> for ShardedFenwickTree<V>
+where
+    V: Group,
+{
+    fn from(tree: FenwickTree<V>) -> Self {
+        ShardedFenwickTree {
+            capacity: tree.capacity,
+            shards: vec![Mutex::new(tree)],
+        }
+    }
+}
+
+impl<V> CumlMap for ShardedFenwickTree<V>
+where
+    V: Group,
+{
+    type Key = usize;
+    type Value = V;
+
+    fn insert(&mut self, key: Self::Key, val: Self::Value) {
+        ShardedFenwickTree::insert(self, key, val);
+    }
+
+    fn set(&mut self, key: Self::Key, val: Self::Value) {
+        assert!(key < self.capacity);
+        self.remove(key);
+        self.shards_mut().next().unwrap().insert(key, val);
+    }
+
+    fn remove(&mut self, key: Self::Key) -> Self::Value {
+        self.shards_mut()
+            .fold(V::identity(), |acc, s| acc.combine(&s.remove(key)))
+    }
+
+    fn get_cuml(&self, key: Self::Key) -> Self::Value {
+        let shards = self.lock_all();
+        prefix_sum(self.capacity, |i| node(&shards, i), key)
+    }
+
+    fn get_single(&self, key: Self::Key) -> Self::Value {
+        let shards = self.lock_all();
+        single_value(self.capacity, |i| node(&shards, i), key)
+    }
+
+    fn get_range<R>(&self, range: R) -> Self::Value
+    where
+        R: RangeBounds<Self::Key>,
+    {
+        let (lo, hi) = clamp_range(range, 0, self.capacity);
+        let shards = self.lock_all();
+        range_sum(self.capacity, |i| node(&shards, i), lo, hi)
+    }
+
+    fn get_quantile(&self, quant: Self::Value) -> Option<Self::Key>
+    where
+        V: PartialOrd,
+    {
+        let shards = self.lock_all();
+        quantile(self.capacity, |i| node(&shards, i), quant)
+    }
+}
@@ -1,5 +1,3 @@
-#![feature(test)]
-#![feature(nll)]
 #![warn(missing_docs)]
 
 //! This crate provides a trait, `CumlMap`, representing a mapping between
@@ -17,13 +15,82 @@
 //! cannot be changed. If you need to get around any of these limitations
 //! then use the `ExtensibleFenwickTree` object instead.
 //!
+//! Both take keys of any primitive integer type, given as their second type
+//! parameter: `usize` by default for `FenwickTree`, and `i64` for
+//! `ExtensibleFenwickTree`.
+//!
 //! Both `FenwickTree` and `ExtensibleFenwickTree` may be a poor choice
 //! for sparse keys. Both structures must allocate space for at least every
 //! possible key between the smallest and largest keys. To get around this
 //! limitation use the `CumlMap` structure, which dynamically allocates
 //! mappings, at the expense of insertion and lookup performance.
+//!
+//! The values stored in any of these mappings may be of any type
+//! implementing the `Group` trait, which provides an identity, a way to
+//! combine two values, and a way to undo that combination. Implementations
+//! are provided for the primitive numeric types, `std::num::Wrapping`,
+//! tuples and vectors. Only `get_quantile` additionally requires the values
+//! to be ordered.
+//!
+//! With the `serde` feature enabled, all three mappings implement
+//! `Serialize` and `Deserialize`. They are stored as their key extent (for
+//! the Fenwick trees) and their `(key, value)` entries in key order, rather
+//! than their internal layout, and are checked for consistency when loaded.
+//!
+//! `ConcurrentFenwickTree` is a `FenwickTree` of atomic integers, into which
+//! many threads may insert at once without locking. `ShardedFenwickTree`
+//! instead keeps one `FenwickTree` per shard of threads, merging them on
+//! query, and works for any values.
+//!
+//! `RangeUpdateFenwickTree` and `RangeUpdateRangeQueryFenwickTree` can add
+//! a value to every key in a range at once, with `insert_range`. The first
+//! answers only single values quickly, while the second also answers
+//! cumulative values and ranges in logarithmic time.
+//!
+//! `FenwickTree2D` and `FenwickTreeND` extend `FenwickTree` to grids of
+//! keys with two or more coordinates, summing the values over boxes.
+//!
+//! `CompressedFenwickTree` takes a universe of keys of any ordered type,
+//! fixed in advance, and maps each to a position in a `FenwickTree` by
+//! binary search, so it suits sparse keys known up front, such as user IDs,
+//! using far less memory than a `FenwickTree` and less time than a
+//! `CumlTree`.
+//!
+//! `SparseFenwickTree` covers every `u64` key without knowing them in
+//! advance, keeping its nodes in a `HashMap` and allocating only those which
+//! an update touches.
+//!
+//! `SegmentTree` answers range queries for any associative operation with an
+//! identity, such as `Min`, `Max` or `Gcd`, which need not be invertible, and
+//! supports lazy range assignment and addition.
+//!
+//! `FenwickTree`, `ExtensibleFenwickTree`, `CumlTree`,
+//! `ConcurrentFenwickTree`, `ShardedFenwickTree`, `CompressedFenwickTree`
+//! and `SparseFenwickTree` have `merge_from` and `subtract` methods, which
+//! add or subtract another mapping of the same type key by key, in time
+//! linear in the size of the two mappings.
+//!
+//! `FenwickTree` has fallible `try_insert`, `try_get_cuml` and similar
+//! methods, which return an `Error` for keys beyond its capacity rather than
+//! panicking, and both Fenwick trees implement `CheckedCumlMap`, whose
+//! methods also report arithmetic overflow, for values implementing
+//! `CheckedAdd` and `CheckedSub`.
+//!
+//! How the Fenwick trees handle overflow is chosen by their value type:
+//! `Checked<T>` panics in every build, while `std::num::Wrapping<T>` and
+//! `std::num::Saturating<T>` wrap around or saturate, as described for
+//! `FenwickTree`.
+//!
+//! A `FenwickTree` of primitive numbers can also be written as a compact
+//! binary snapshot, which `FenwickTreeView` can query in place, for example
+//! from a memory-mapped file, without copying it.
 
 extern crate num_traits;
+#[cfg(feature = "serde")]
+extern crate serde;
+
+mod algebra;
+pub use algebra::*;
 
 mod cmap;
 pub use cmap::*;
@@ -31,8 +98,29 @@ pub use cmap::*;
 mod bix;
 pub use bix::*;
 
+mod bixnd;
+pub use bixnd::*;
+
 mod rctree;
 pub use rctree::*;
 
+mod compressed;
+pub use compressed::*;
+
+mod sparse;
+pub use sparse::*;
+
+mod range;
+pub use range::*;
+
+mod segment;
+pub use segment::*;
+
+mod snapshot;
+pub use snapshot::*;
+
+mod concurrent;
+pub use concurrent::*;
+
 #[cfg(test)]
 mod tests;
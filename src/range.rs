@@ -0,0 +1,338 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::RangeBounds;
+
+use algebra::*;
+use bix::*;
+use cmap::*;
+
+/*****************************************************************************
+ * Binary Index Trees over differences, for range updates
+ *****************************************************************************/
+
+/// Get the inverse of `v`.
+fn negate<V: Group>(v: &V) -> V {
+    V::identity().combine_inverse(v)
+}
+
+/// Check that the inclusive range `[lo, hi]` is a non-empty range of keys
+/// within the capacity of a tree.
+fn check_range(lo: usize, hi: usize, capacity: usize) {
+    assert!(lo <= hi, "Trying to insert into an empty range");
+    assert!(hi < capacity, "Trying to insert into a range beyond the tree's capacity");
+}
+
+/// A map over the keys `0..capacity` which can add a value to every key in
+/// a range at once. It stores the differences between the values at
+/// neighbouring keys in a `FenwickTree`, so that both `insert_range` and
+/// `get_single` take `O(log n)` time. However, `get_cuml`, `get_range` and
+/// `get_quantile` take `O(k + log n)` time for the `k` keys they cover, as
+/// they carry the value at each key forward to the next rather than looking
+/// each one up; if those are needed often, use a
+/// `RangeUpdateRangeQueryFenwickTree` instead.
+///
+/// Since the differences may be negative even when every value is positive,
+/// values must have true inverses: use signed integers, floats or
+/// `std::num::Wrapping`, rather than plain unsigned integers.
+///
+/// # Examples
+/// ```
+/// use cuml_map::{CumlMap, RangeUpdateFenwickTree};
+///
+/// // Rooms booked on each night of a month.
+/// let mut booked: RangeUpdateFenwickTree<i32> = RangeUpdateFenwickTree::with_capacity(31);
+/// booked.insert_range(3, 9, 2);
+/// booked.insert_range(7, 12, 1);
+/// assert_eq!(booked.get_single(8), 3);
+/// assert_eq!(booked.get_single(10), 1);
+/// assert_eq!(booked.get_range(..5), 4);
+/// ```
+#[derive(Clone)]
+pub struct RangeUpdateFenwickTree<V> {
+    diffs: FenwickTree<V>,
+}
+
+impl<V> RangeUpdateFenwickTree<V>
+where
+    V: Group,
+{
+    /// Create a `RangeUpdateFenwickTree` object with a fixed capacity.
+    pub fn with_capacity(c: usize) -> Self {
+        RangeUpdateFenwickTree {
+            diffs: FenwickTree::with_capacity(c),
+        }
+    }
+
+    /// Get the number of keys covered by the tree.
+    pub fn capacity(&self) -> usize {
+        self.diffs.capacity
+    }
+
+    /// Add `val` to the value at every key from `lo` to `hi` inclusive.
+    ///
+    /// # Panics
+    /// Panics if `lo` is greater than `hi`, or `hi` is beyond the tree's
+    /// capacity.
+    pub fn insert_range(&mut self, lo: usize, hi: usize, val: V) {
+        check_range(lo, hi, self.capacity());
+        if hi + 1 < self.capacity() {
+            self.diffs.insert(hi + 1, negate(&val));
+        }
+        self.diffs.insert(lo, val);
+    }
+
+    /// Iterate over every `(key, value)` pair in the tree, in key order.
+    pub fn iter(&self) -> Cumulative<FenwickIter<'_, V>, V> {
+        self.diffs.iter().cumulative()
+    }
+}
+
+impl<V> CumlMap for RangeUpdateFenwickTree<V>
+where
+    V: Group,
+{
+    type Key = usize;
+    type Value = V;
+
+    fn insert(&mut self, key: Self::Key, val: Self::Value) {
+        self.insert_range(key, key, val);
+    }
+
+    fn set(&mut self, key: Self::Key, val: Self::Value) {
+        let old = self.get_single(key);
+        self.insert(key, val.combine_inverse(&old));
+    }
+
+    fn remove(&mut self, key: Self::Key) -> Self::Value {
+        if key >= self.capacity() {
+            return V::identity();
+        }
+        let old = self.get_single(key);
+        self.insert(key, negate(&old));
+        old
+    }
+
+    fn get_cuml(&self, key: Self::Key) -> Self::Value {
+        self.get_range(..=key)
+    }
+
+    fn get_single(&self, key: Self::Key) -> Self::Value {
+        if key >= self.capacity() {
+            return V::identity();
+        }
+        self.diffs.get_cuml(key)
+    }
+
+    fn get_range<R>(&self, range: R) -> Self::Value
+    where
+        R: RangeBounds<Self::Key>,
+    {
+        let (lo, hi) = clamp_range(range, 0, self.capacity());
+        // The value at each key is the cumulative difference there, which
+        // the iterator carries forward from key to key: after the prefix at
+        // `lo`, each step reads `O(1)` nodes on average.
+        self.diffs
+            .range(lo..hi)
+            .cumulative()
+            .fold(V::identity(), |acc, (_, v)| acc.combine(&v))
+    }
+
+    fn get_quantile(&self, quant: Self::Value) -> Option<Self::Key>
+    where
+        V: PartialOrd,
+    {
+        quant.partial_cmp(&quant)?;
+        let mut total = V::identity();
+        for (k, v) in self.iter() {
+            total = total.combine(&v);
+            if total.partial_cmp(&quant) != Some(Ordering::Less) {
+                return Some(k);
+            }
+        }
+        None
+    }
+}
+
+impl<V: Group> Default for RangeUpdateFenwickTree<V> {
+    /// Create a `RangeUpdateFenwickTree` with zero capacity.
+    fn default() -> Self {
+        Self::with_capacity(0)
+    }
+}
+
+impl<V: Group + fmt::Debug> fmt::Debug for RangeUpdateFenwickTree<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let zero = V::identity();
+        f.debug_map().entries(self.iter().filter(|e| e.1 != zero)).finish()
+    }
+}
+
+/// A map over the keys `0..capacity` which can add a value to every key in
+/// a range at once, and sum the values over any range, each in `O(log n)`
+/// time. This uses the usual pair of `FenwickTree`s: one of the differences
+/// `d[j]` between the values at neighbouring keys, and one of `j * d[j]`, so
+/// that the cumulative value at `k` is `(k + 1) * (d[0] + ... + d[k])` less
+/// `0 * d[0] + ... + k * d[k]`.
+///
+/// As for `RangeUpdateFenwickTree`, values must have true inverses. Since
+/// values are multiplied by keys along the way, they must also be able to
+/// hold the capacity times the largest value at any key without overflow.
+///
+/// # Examples
+/// ```
+/// use cuml_map::{CumlMap, RangeUpdateRangeQueryFenwickTree};
+///
+/// let mut rt: RangeUpdateRangeQueryFenwickTree<i64> =
+///     RangeUpdateRangeQueryFenwickTree::with_capacity(100);
+/// rt.insert_range(10, 19, 3);
+/// rt.insert_range(15, 49, 1);
+/// assert_eq!(rt.get_single(16), 4);
+/// assert_eq!(rt.get_cuml(20), 36);
+/// assert_eq!(rt.get_range(15..25), 25);
+/// assert_eq!(rt.get_quantile(30), Some(18));
+/// ```
+#[derive(Clone)]
+pub struct RangeUpdateRangeQueryFenwickTree<V> {
+    diffs: FenwickTree<V>,
+    weighted: FenwickTree<V>,
+}
+
+impl<V> RangeUpdateRangeQueryFenwickTree<V>
+where
+    V: Group,
+{
+    /// Create a `RangeUpdateRangeQueryFenwickTree` object with a fixed
+    /// capacity.
+    pub fn with_capacity(c: usize) -> Self {
+        RangeUpdateRangeQueryFenwickTree {
+            diffs: FenwickTree::with_capacity(c),
+            weighted: FenwickTree::with_capacity(c),
+        }
+    }
+
+    /// Get the number of keys covered by the tree.
+    pub fn capacity(&self) -> usize {
+        self.diffs.capacity
+    }
+
+    /// Add `val` to the value at every key from `lo` to `hi` inclusive.
+    ///
+    /// # Panics
+    /// Panics if `lo` is greater than `hi`, or `hi` is beyond the tree's
+    /// capacity.
+    pub fn insert_range(&mut self, lo: usize, hi: usize, val: V) {
+        check_range(lo, hi, self.capacity());
+        if hi + 1 < self.capacity() {
+            self.add_diff(hi + 1, negate(&val));
+        }
+        self.add_diff(lo, val);
+    }
+
+    /// Iterate over every `(key, value)` pair in the tree, in key order.
+    pub fn iter(&self) -> Cumulative<FenwickIter<'_, V>, V> {
+        self.diffs.iter().cumulative()
+    }
+
+    /// Add `val` to the difference between the values at `key` and the key
+    /// before it.
+    fn add_diff(&mut self, key: usize, val: V) {
+        self.weighted.insert(key, times(&val, key));
+        self.diffs.insert(key, val);
+    }
+
+    /// Get the cumulative value at `key`, which must be within capacity.
+    fn prefix(&self, key: usize) -> V {
+        times(&self.diffs.get_cuml(key), key + 1).combine_inverse(&self.weighted.get_cuml(key))
+    }
+}
+
+impl<V> CumlMap for RangeUpdateRangeQueryFenwickTree<V>
+where
+    V: Group,
+{
+    type Key = usize;
+    type Value = V;
+
+    fn insert(&mut self, key: Self::Key, val: Self::Value) {
+        self.insert_range(key, key, val);
+    }
+
+    fn set(&mut self, key: Self::Key, val: Self::Value) {
+        let old = self.get_single(key);
+        self.insert(key, val.combine_inverse(&old));
+    }
+
+    fn remove(&mut self, key: Self::Key) -> Self::Value {
+        if key >= self.capacity() {
+            return V::identity();
+        }
+        let old = self.get_single(key);
+        self.insert(key, negate(&old));
+        old
+    }
+
+    fn get_cuml(&self, key: Self::Key) -> Self::Value {
+        match self.capacity() {
+            0 => V::identity(),
+            c => self.prefix(key.min(c - 1)),
+        }
+    }
+
+    fn get_single(&self, key: Self::Key) -> Self::Value {
+        if key >= self.capacity() {
+            return V::identity();
+        }
+        self.diffs.get_cuml(key)
+    }
+
+    fn get_range<R>(&self, range: R) -> Self::Value
+    where
+        R: RangeBounds<Self::Key>,
+    {
+        let (lo, hi) = clamp_range(range, 0, self.capacity());
+        if lo >= hi {
+            V::identity()
+        } else if lo == 0 {
+            self.prefix(hi - 1)
+        } else {
+            self.prefix(hi - 1).combine_inverse(&self.prefix(lo - 1))
+        }
+    }
+
+    fn get_quantile(&self, quant: Self::Value) -> Option<Self::Key>
+    where
+        V: PartialOrd,
+    {
+        quant.partial_cmp(&quant)?;
+        // Binary search for the first key whose cumulative value is not below
+        // `quant`, in `O(log^2 n)` time.
+        let (mut lo, mut hi) = (0, self.capacity());
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if self.prefix(mid) < quant {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        if lo < self.capacity() {
+            Some(lo)
+        } else {
+            None
+        }
+    }
+}
+
+impl<V: Group> Default for RangeUpdateRangeQueryFenwickTree<V> {
+    /// Create a `RangeUpdateRangeQueryFenwickTree` with zero capacity.
+    fn default() -> Self {
+        Self::with_capacity(0)
+    }
+}
+
+impl<V: Group + fmt::Debug> fmt::Debug for RangeUpdateRangeQueryFenwickTree<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let zero = V::identity();
+        f.debug_map().entries(self.iter().filter(|e| e.1 != zero)).finish()
+    }
+}
@@ -1,13 +1,16 @@
 extern crate num_traits;
-use num_traits::Zero;
-use std::ops::{Add, Sub};
-use std::ptr;
-use std::cmp::{PartialEq, Eq, Ordering};
+use std::ops::{Bound, RangeBounds};
+use std::iter::FromIterator;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::cmp::{self, Ordering};
 
+use algebra::*;
 use cmap::*;
 
 /*****************************************************************************
- * Cumulative frequency tree with raw pointers, Red-Black balancing
+ * Cumulative frequency tree in an arena, Red-Black balancing
  *****************************************************************************/
 
 #[derive(PartialEq,Clone,Copy,Debug)]
@@ -16,366 +19,1198 @@ enum Color {
     Black,
 }
 
+/// The position of a node within its tree's arena.
+type NodeId = u32;
+
+/// The null link, used for missing children and for the root's parent.
+const NIL: NodeId = u32::MAX;
+
+#[derive(Clone)]
 struct CumlNode<K, V> {
     index: K,
     val: V,
-    left: NodeRef<K, V>,
-    right: NodeRef<K, V>,
-    parent: NodeRef<K, V>,
+    left: NodeId,
+    right: NodeId,
+    parent: NodeId,
     color: Color,
 }
 
 type Node<K, V> = CumlNode<K, V>;
-struct NodeRef<K, V>(*mut CumlNode<K, V>);
 
-impl<K, V> Clone for NodeRef<K, V> {
-    fn clone(&self) -> NodeRef<K, V> {
-        NodeRef(self.0)
-    }
+/// A slot in the arena, which either holds a node or is free. Free slots are
+/// chained into a list through the id of the next free slot.
+#[derive(Clone)]
+enum Slot<K, V> {
+    Occupied(Node<K, V>),
+    Vacant(NodeId),
 }
 
-impl<K, V> PartialEq for NodeRef<K, V> {
-    fn eq(&self, other: &NodeRef<K, V>) -> bool {
-        self.0 == other.0
+impl<K, V> Slot<K, V> {
+    fn node(&self) -> &Node<K, V> {
+        match self {
+            Slot::Occupied(nv) => nv,
+            Slot::Vacant(_) => panic!("Trying to follow a link to a freed node"),
+        }
     }
-}
 
-impl<K, V> Eq for NodeRef<K, V> {}
-
-impl<K, V> Copy for NodeRef<K, V> {}
-
-impl<K, V> NodeRef<K, V> {
-    fn null() -> NodeRef<K, V> {
-        NodeRef(ptr::null_mut())
+    fn node_mut(&mut self) -> &mut Node<K, V> {
+        match self {
+            Slot::Occupied(nv) => nv,
+            Slot::Vacant(_) => panic!("Trying to follow a link to a freed node"),
+        }
     }
+}
 
-    fn new(k: K, v: V, p: NodeRef<K, V>) -> NodeRef<K, V> {
-        NodeRef(Box::into_raw(Box::new(CumlNode {
-            index: k,
-            val: v,
-            left: Self::null(),
-            right: Self::null(),
-            parent: p,
-            color: Color::Red,
-        })))
+/// Whether key `k` falls before the range starting at bound `b`.
+pub(crate) fn before_start<K: Ord>(b: Bound<&K>, k: &K) -> bool {
+    match b {
+        Bound::Included(lo) => k < lo,
+        Bound::Excluded(lo) => k <= lo,
+        Bound::Unbounded => false,
     }
+}
 
-    unsafe fn free(&mut self) {
-        if !self.0.is_null() {
-            self.borrow_mut().map(|x| x.left().free());
-            self.borrow_mut().map(|x| x.right().free());
-            Box::from_raw(self.0);
-        }
+/// Whether key `k` falls before the end of the range ending at bound `b`.
+pub(crate) fn before_end<K: Ord>(b: Bound<&K>, k: &K) -> bool {
+    match b {
+        Bound::Included(hi) => k <= hi,
+        Bound::Excluded(hi) => k < hi,
+        Bound::Unbounded => true,
     }
+}
 
-    fn borrow_mut(&self) -> Option<&mut Node<K, V>> {
-        if self.0.is_null() {
-            None
-        } else {
-            unsafe { Some(&mut *self.0) }
+/// The `CumlTree` type. An unbounded mapping between ordered keys and
+/// cumulative values, represented as a red-black tree.
+///
+/// By default a key stays in the tree once it has been inserted, even if its
+/// value is later driven back to zero; use `remove` to drop it, or create
+/// the tree with `with_pruning` to have such keys dropped automatically.
+///
+/// Keys need only be ordered. The `CumlMap` implementation also requires
+/// them to be `Clone`, since `get_quantile` returns an owned key, but the
+/// tree's own methods (such as `iter` and `get_quantile_ref`) hand out
+/// references instead.
+///
+/// Nodes are kept in a single vector and linked by their positions in it,
+/// so a tree can hold at most `u32::MAX` keys. Since the tree holds no
+/// pointers, it is `Send` and `Sync` whenever its keys and values are, and
+/// a tree built on one thread may be queried from many others at once.
+///
+/// Two trees compare equal if they hold the same value at every key, so a
+/// key left in a tree with a zero value is the same as one never inserted,
+/// and `Debug` prints only the keys with non-zero values.
+#[derive(Clone)]
+pub struct CumlTree<K, V> {
+    nodes: Vec<Slot<K, V>>,
+    free: NodeId,
+    root: NodeId,
+    len: usize,
+    prune: bool,
+}
+
+impl<K, V> CumlTree<K, V> {
+    /// Create an empty `CumlTree` object.
+    ///
+    /// # Examples
+    /// ```
+    /// use cuml_map::CumlTree;
+    ///
+    /// let ct: CumlTree<i32, i32> = CumlTree::new();
+    /// ```
+    pub fn new() -> Self {
+        CumlTree {
+            nodes: Vec::new(),
+            free: NIL,
+            root: NIL,
+            len: 0,
+            prune: false,
         }
     }
-}
 
-impl<K, V> Node<K, V> {
-    fn left(&self) -> NodeRef<K, V> {
-        self.left
+    /// Create an empty `CumlTree` object which removes any key whose value
+    /// becomes zero through `insert` or `set`, so that long-lived trees with
+    /// churning keys do not grow without bound.
+    ///
+    /// # Examples
+    /// ```
+    /// use cuml_map::{CumlMap, CumlTree};
+    ///
+    /// let mut ct: CumlTree<i32, i32> = CumlTree::with_pruning();
+    /// ct.insert(3, 5);
+    /// ct.insert(3, -5);
+    /// assert!(ct.is_empty());
+    /// ```
+    pub fn with_pruning() -> Self {
+        CumlTree {
+            prune: true,
+            ..Self::new()
+        }
     }
 
-    fn right(&self) -> NodeRef<K, V> {
-        self.right
+    /// Get the number of keys currently stored in the tree.
+    pub fn len(&self) -> usize {
+        self.len
     }
 
-    fn parent(&self) -> NodeRef<K, V> {
-        self.parent
+    /// Check whether the tree contains no keys.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
     }
 
-    fn color(&self) -> Color {
-        self.color
+    fn node(&self, n: NodeId) -> &Node<K, V> {
+        self.nodes[n as usize].node()
     }
 
-    fn set_val(&mut self, val: V) {
-        self.val = val
+    fn node_mut(&mut self, n: NodeId) -> &mut Node<K, V> {
+        self.nodes[n as usize].node_mut()
     }
 
-    fn set_left(&mut self, l: NodeRef<K, V>) {
-        self.left = l
+    /// Get the node `n`, or `None` if `n` is the null link.
+    fn get(&self, n: NodeId) -> Option<&Node<K, V>> {
+        if n == NIL {
+            None
+        } else {
+            Some(self.node(n))
+        }
     }
 
-    fn set_right(&mut self, r: NodeRef<K, V>) {
-        self.right = r
+    /// Mutably borrow two distinct nodes at once.
+    fn node_pair_mut(&mut self, a: NodeId, b: NodeId) -> (&mut Node<K, V>, &mut Node<K, V>) {
+        assert!(a != b, "Trying to borrow a node twice");
+        let (lo, hi) = (cmp::min(a, b) as usize, cmp::max(a, b) as usize);
+        let (first, rest) = self.nodes.split_at_mut(hi);
+        let (lo, hi) = (first[lo].node_mut(), rest[0].node_mut());
+        if a < b { (lo, hi) } else { (hi, lo) }
     }
 
-    fn set_parent(&mut self, p: NodeRef<K, V>) {
-        self.parent = p
+    fn color(&self, n: NodeId) -> Color {
+        match self.get(n) {
+            None => Color::Black,
+            Some(nv) => nv.color,
+        }
     }
 
-    fn recolor(&mut self, c: Color) {
-        self.color = c
+    fn recolor(&mut self, n: NodeId, c: Color) {
+        self.node_mut(n).color = c
     }
 
-    fn left_child_eq(&self, other: NodeRef<K, V>) -> bool {
-        other == self.left
+    /// Store a new red node with no children, reusing a free slot if there
+    /// is one.
+    fn alloc(&mut self, k: K, v: V, p: NodeId) -> NodeId {
+        let node = Slot::Occupied(CumlNode {
+            index: k,
+            val: v,
+            left: NIL,
+            right: NIL,
+            parent: p,
+            color: Color::Red,
+        });
+        if self.free == NIL {
+            let n = self.nodes.len();
+            assert!(n < NIL as usize, "CumlTree cannot hold more than u32::MAX keys");
+            self.nodes.push(node);
+            n as NodeId
+        } else {
+            let n = self.free;
+            match mem::replace(&mut self.nodes[n as usize], node) {
+                Slot::Vacant(next) => self.free = next,
+                Slot::Occupied(_) => panic!("Free list links to an occupied slot"),
+            }
+            n
+        }
     }
 
-    fn right_child_eq(&self, other: NodeRef<K, V>) -> bool {
-        other == self.right
+    /// Drop node `n`, and add its slot to the free list.
+    fn release(&mut self, n: NodeId) {
+        self.nodes[n as usize] = Slot::Vacant(self.free);
+        self.free = n;
     }
 
-    fn swap_child(&mut self, old: NodeRef<K, V>, new: NodeRef<K, V>) {
-        if old == self.left {
-            self.left = new;
-        } else if old == self.right {
-            self.right = new;
+    /// Point whichever link led from `p` to `old` (or the root link, if `p`
+    /// is null) at `new` instead.
+    fn replace_child(&mut self, p: NodeId, old: NodeId, new: NodeId) {
+        if p == NIL {
+            self.root = new;
+            return;
+        }
+        let pv = self.node_mut(p);
+        if old == pv.left {
+            pv.left = new;
+        } else if old == pv.right {
+            pv.right = new;
         } else {
             panic!("Trying to swap child, but node given is not current child");
         }
     }
 
-    fn other_child(&self, c: NodeRef<K, V>) -> NodeRef<K, V> {
-        if c == self.left {
-            self.right
-        } else if c == self.right {
-            self.left
+    fn other_child(&self, p: NodeId, c: NodeId) -> NodeId {
+        let pv = self.node(p);
+        if c == pv.left {
+            pv.right
+        } else if c == pv.right {
+            pv.left
         } else {
             panic!("Trying to pick other child, but node given is not current child");
         }
     }
-}
 
-impl<K: Clone, V> Node<K, V> {
-    fn index(&self) -> K {
-        self.index.clone()
+    /// Get the next node in key order, following parent links as needed.
+    fn successor(&self, n: NodeId) -> NodeId {
+        let mut s = self.node(n).right;
+        if s != NIL {
+            while self.node(s).left != NIL {
+                s = self.node(s).left;
+            }
+            return s;
+        }
+        let mut c = n;
+        s = self.node(n).parent;
+        while s != NIL && self.node(s).right == c {
+            c = s;
+            s = self.node(s).parent;
+        }
+        s
     }
-}
 
-impl<K, V: Clone> Node<K, V> {
-    fn val(&self) -> V {
-        self.val.clone()
+    /// Get the previous node in key order, following parent links as needed.
+    fn predecessor(&self, n: NodeId) -> NodeId {
+        let mut s = self.node(n).left;
+        if s != NIL {
+            while self.node(s).right != NIL {
+                s = self.node(s).right;
+            }
+            return s;
+        }
+        let mut c = n;
+        s = self.node(n).parent;
+        while s != NIL && self.node(s).left == c {
+            c = s;
+            s = self.node(s).parent;
+        }
+        s
     }
 }
 
-impl<K, V> Node<K, V>
+impl<K, V> CumlTree<K, V>
 where
-    K: Add<Output = K> + Sub<Output = K> + Zero + Clone + Ord,
-    V: Add<Output = V> + Sub<Output = V> + Zero + Clone + Ord,
+    K: Ord,
+    V: Group,
 {
-    fn get_total(&self) -> V {
-        match self.right().borrow_mut() {
-            None => self.val(),
-            Some(r) => self.val() + r.get_total(),
+    /// Sum the values of every key in the subtree rooted at `n`.
+    fn get_total(&self, n: NodeId) -> V {
+        let mut n = n;
+        let mut acc = V::identity();
+        while let Some(nv) = self.get(n) {
+            acc = acc.combine(&nv.val);
+            n = nv.right;
         }
+        acc
     }
 
-    fn get_cuml(&self, k: K, acc: V) -> V {
-        match (k.cmp(&self.index()), self.left().borrow_mut(), self.right().borrow_mut()) {
-            (Ordering::Less, Some(l), _) => l.get_cuml(k, acc),
-            (Ordering::Less, _, _) => acc,
-            (Ordering::Greater, _, Some(r)) => r.get_cuml(k, acc + self.val()),
-            (_, _, _) => acc + self.val(),
+    /// Get the value stored at node `n` alone.
+    fn single(&self, n: NodeId) -> V {
+        let nv = self.node(n);
+        match self.get(nv.left) {
+            None => nv.val.clone(),
+            Some(_) => nv.val.combine_inverse(&self.get_total(nv.left)),
         }
     }
 
-    fn get_single(&self, k: K) -> V {
-        match (k.cmp(&self.index()), self.left().borrow_mut(), self.right().borrow_mut()) {
-            (Ordering::Equal, Some(l), _) => self.val() - l.get_total(),
-            (Ordering::Equal, _, _) => self.val(),
-            (Ordering::Less, Some(l), _) => l.get_single(k),
-            (Ordering::Greater, _, Some(r)) => r.get_single(k),
-            (_, _, _) => V::zero(),
+    fn rb_fix(&mut self, n: NodeId) {
+        let mut n = n;
+        loop {
+            let mut p = self.node(n).parent;
+            if p == NIL {
+                self.recolor(n, Color::Black);
+                return;
+            }
+            if self.color(p) == Color::Black {
+                return;
+            }
+            let g = self.node(p).parent;
+            let u = self.other_child(g, p);
+            if self.color(u) == Color::Red {
+                self.recolor(p, Color::Black);
+                self.recolor(u, Color::Black);
+                self.recolor(g, Color::Red);
+                n = g;
+                continue;
+            }
+            if self.node(g).left == p && self.node(p).right == n {
+                self.left_rotate(p);
+                p = n;
+            } else if self.node(g).right == p && self.node(p).left == n {
+                self.right_rotate(p);
+                p = n;
+            }
+            if self.node(g).left == p {
+                self.right_rotate(g);
+            } else {
+                self.left_rotate(g);
+            }
+            self.recolor(p, Color::Black);
+            self.recolor(g, Color::Red);
+            return;
         }
     }
 
-    fn get_quantile(&self, v: V) -> Option<K> {
-        match (v.cmp(&self.val()), self.left().borrow_mut(), self.right().borrow_mut()) {
-            (Ordering::Less, Some(l), _) => match l.get_quantile(v) {
-                None => Some(self.index()),
-                s => s,
+    /// Sum the values of all keys in the subtree rooted at `n` which satisfy
+    /// `f`, where `f` must hold for some prefix of the keys in order.
+    fn sum_while<F>(&self, n: NodeId, f: F) -> V
+    where
+        F: Fn(&K) -> bool,
+    {
+        let mut n = n;
+        let mut acc = V::identity();
+        while let Some(nv) = self.get(n) {
+            if f(&nv.index) {
+                acc = acc.combine(&nv.val);
+                n = nv.right;
+            } else {
+                n = nv.left;
+            }
+        }
+        acc
+    }
+
+    /// Iterate over every `(key, value)` pair in the tree, in key order.
+    ///
+    /// # Examples
+    /// ```
+    /// use cuml_map::{CumlMap, CumlTree};
+    ///
+    /// let mut ct: CumlTree<i32, u32> = CumlTree::new();
+    /// ct.insert(5, 1);
+    /// ct.insert(-2, 4);
+    /// ct.insert(9, 2);
+    /// let v: Vec<_> = ct.iter().collect();
+    /// assert_eq!(v, vec![(&-2, 4), (&5, 1), (&9, 2)]);
+    /// let v: Vec<_> = ct.range(0..).cumulative().rev().collect();
+    /// assert_eq!(v, vec![(&9, 7), (&5, 5)]);
+    /// ```
+    pub fn iter(&self) -> CumlTreeIter<'_, K, V> {
+        self.range(..)
+    }
+
+    /// Iterate over every `(key, cumulative value)` pair in the tree, in
+    /// key order.
+    pub fn cuml_iter(&self) -> Cumulative<CumlTreeIter<'_, K, V>, V> {
+        self.iter().cumulative()
+    }
+
+    /// Iterate over the `(key, value)` pairs in the tree whose keys lie
+    /// within the given range, in key order.
+    pub fn range<R>(&self, range: R) -> CumlTreeIter<'_, K, V>
+    where
+        R: RangeBounds<K>,
+    {
+        let (start, end) = (range.start_bound(), range.end_bound());
+        let mut front = NIL;
+        let mut back = NIL;
+        let mut n = self.root;
+        while let Some(nv) = self.get(n) {
+            if before_start(start, &nv.index) {
+                n = nv.right;
+            } else {
+                front = n;
+                n = nv.left;
+            }
+        }
+        n = self.root;
+        while let Some(nv) = self.get(n) {
+            if before_end(end, &nv.index) {
+                back = n;
+                n = nv.right;
+            } else {
+                n = nv.left;
+            }
+        }
+        match (self.get(front), self.get(back)) {
+            (Some(f), Some(b)) if f.index <= b.index => (),
+            _ => {
+                front = NIL;
+                back = NIL;
             },
-            (Ordering::Greater, _, Some(r)) => r.get_quantile(v - self.val()),
-            (Ordering::Greater, _, None) => None,
-            (_, _, _) => Some(self.index()),
+        }
+        CumlTreeIter {
+            tree: self,
+            front,
+            back,
         }
     }
-}
 
-/// The `CumlTree` type. An unbounded mapping between ordered keys and
-/// cumulative values, represented as a red-black tree.
-pub struct CumlTree<K, V> {
-    root: NodeRef<K, V>,
-}
+    /// Create a `CumlTree` object from `(key, value)` pairs sorted by key,
+    /// in linear time. The values of any repeated keys are summed.
+    ///
+    /// # Panics
+    /// Panics if the keys are not in non-decreasing order.
+    ///
+    /// # Examples
+    /// ```
+    /// use cuml_map::{CumlMap, CumlTree};
+    ///
+    /// let ct = CumlTree::from_sorted(vec![(-3, 1), (0, 2), (0, 3), (8, 4)]);
+    /// assert_eq!(ct.get_single(0), 5);
+    /// assert_eq!(ct.get_cuml(7), 6);
+    /// ```
+    pub fn from_sorted<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut items: Vec<(K, V)> = Vec::new();
+        for (k, v) in iter {
+            if let Some(last) = items.last_mut() {
+                assert!(last.0 <= k, "keys passed to from_sorted are out of order");
+                if last.0 == k {
+                    last.1 = last.1.combine(&v);
+                    continue;
+                }
+            }
+            items.push((k, v));
+        }
+        let len = items.len();
+        let full = usize::BITS - (len + 1).leading_zeros() - 1;
+        let mut tree = CumlTree {
+            nodes: Vec::with_capacity(len),
+            len,
+            ..Self::new()
+        };
+        let (root, _) = tree.build(&mut items.into_iter(), len, 0, full);
+        tree.root = root;
+        tree
+    }
 
-impl<K, V> CumlTree<K, V> {
-    /// Create an empty `CumlTree` object.
-    /// 
+    /// Add the value at every key of `other` to the value at the same key in
+    /// this tree. If `other` is small enough, its entries are inserted one at
+    /// a time; otherwise the entries of both trees are merged in key order
+    /// and the tree rebuilt, in time linear in the size of the two trees.
+    ///
     /// # Examples
     /// ```
-    /// use cuml_map::CumlTree;
-    /// 
-    /// let ct: CumlTree<i32, i32> = CumlTree::new();
+    /// use cuml_map::{CumlMap, CumlTree};
+    ///
+    /// let mut a: CumlTree<&str, u32> = vec![("ant", 2), ("cat", 1)].into_iter().collect();
+    /// let b: CumlTree<&str, u32> = vec![("bee", 4), ("cat", 3)].into_iter().collect();
+    /// a.merge_from(&b);
+    /// assert_eq!(a.get_single("cat"), 4);
+    /// assert_eq!(a.get_cuml("bee"), 6);
     /// ```
-    pub fn new() -> Self {
-        CumlTree { root: NodeRef::null() }
+    pub fn merge_from(&mut self, other: &Self)
+    where
+        K: Clone,
+    {
+        self.merge_with(other, V::combine);
     }
-}
 
-impl<K, V> Drop for CumlTree<K, V> {
-    fn drop(&mut self) {
-        unsafe { self.root.free(); }
+    /// Subtract the value at every key of `other` from the value at the same
+    /// key in this tree, in the same way as `merge_from`. Keys only in
+    /// `other` are added to the tree, with the inverse of their values.
+    ///
+    /// # Examples
+    /// ```
+    /// use cuml_map::{CumlMap, CumlTree};
+    ///
+    /// let mut a: CumlTree<i32, i32> = vec![(1, 2), (5, 1)].into_iter().collect();
+    /// let b: CumlTree<i32, i32> = vec![(3, 4), (5, 3)].into_iter().collect();
+    /// a.subtract(&b);
+    /// assert_eq!(a.get_single(5), -2);
+    /// assert_eq!(a.get_cuml(4), -2);
+    /// ```
+    pub fn subtract(&mut self, other: &Self)
+    where
+        K: Clone,
+    {
+        self.merge_with(other, V::combine_inverse);
     }
-}
 
-impl<K, V> CumlTree<K, V>
-where
-    K: Add<Output = K> + Sub<Output = K> + Zero + Clone + Ord,
-    V: Add<Output = V> + Sub<Output = V> + Zero + Clone + Ord,
-{
-    fn rb_fix(&mut self, np: NodeRef<K, V>) {
-        let nv = np.borrow_mut().unwrap();
-        let mut pp = nv.parent();
-        if let Some(mut pv) = pp.borrow_mut() {
-            if pv.color() == Color::Red {
-                let gp = pv.parent();
-                let gv = gp.borrow_mut().unwrap();
-                let up = gv.other_child(pp);
-                if let Some(uv) = up.borrow_mut() {
-                    if uv.color() == Color::Red {
-                        pv.recolor(Color::Black);
-                        uv.recolor(Color::Black);
-                        gv.recolor(Color::Red);
-                        self.rb_fix(gp);
-                    } else {
-                        if gv.left_child_eq(pp) && pv.right_child_eq(np) {
-                            unsafe { self.left_rotate(pp) };
-                            pp = np;
-                            pv = nv;
-                        } else if gv.right_child_eq(pp) && pv.left_child_eq(np) {
-                            unsafe { self.right_rotate(pp) };
-                            pp = np;
-                            pv = nv;
-                        }
-                        if gv.left_child_eq(pp) {
-                            unsafe { self.right_rotate(gp) };
-                        } else {
-                            unsafe { self.left_rotate(gp) };
-                        }
-                        pv.recolor(Color::Black);
-                        gv.recolor(Color::Red);
+    /// Replace the value `x` at every key with `f(x, y)`, where `y` is the
+    /// value at the same key of `other`, and `f(x, 0)` must equal `x`.
+    fn merge_with<F>(&mut self, other: &Self, f: F)
+    where
+        K: Clone,
+        F: Fn(&V, &V) -> V,
+    {
+        // Inserting m keys one by one costs about m log (n + m), against
+        // n + m for rebuilding the whole tree.
+        let log = (usize::BITS - (self.len + other.len).leading_zeros()) as usize;
+        if other.len * log < self.len + other.len {
+            for (k, v) in other.iter() {
+                let n = self.apply(k.clone(), |x| f(x, &v));
+                self.prune(n);
+            }
+            return;
+        }
+
+        let prune = self.prune;
+        let mine = mem::take(self).into_sorted();
+        let mut theirs = other.iter().peekable();
+        let mut merged = Vec::with_capacity(mine.len() + other.len);
+        for (k, v) in mine {
+            while let Some((j, w)) = theirs.next_if(|e| e.0 < &k) {
+                merged.push((j.clone(), f(&V::identity(), &w)));
+            }
+            match theirs.next_if(|e| e.0 == &k) {
+                Some((_, w)) => merged.push((k, f(&v, &w))),
+                None => merged.push((k, v)),
+            }
+        }
+        for (j, w) in theirs {
+            merged.push((j.clone(), f(&V::identity(), &w)));
+        }
+        if prune {
+            let zero = V::identity();
+            merged.retain(|e| e.1 != zero);
+        }
+        *self = CumlTree::from_sorted(merged);
+        self.prune = prune;
+    }
+
+    /// Take every `(key, value)` pair out of the tree, in key order.
+    fn into_sorted(self) -> Vec<(K, V)> {
+        let mut order = Vec::with_capacity(self.len);
+        let mut n = self.root;
+        while let Some(nv) = self.get(n) {
+            if nv.left == NIL {
+                break;
+            }
+            n = nv.left;
+        }
+        while n != NIL {
+            order.push((n, self.single(n)));
+            n = self.successor(n);
+        }
+        let mut nodes = self.nodes;
+        order
+            .into_iter()
+            .map(|(n, v)| match mem::replace(&mut nodes[n as usize], Slot::Vacant(NIL)) {
+                Slot::Occupied(nv) => (nv.index, v),
+                Slot::Vacant(_) => panic!("Trying to follow a link to a freed node"),
+            })
+            .collect()
+    }
+
+    /// Build a balanced subtree out of the next `n` items, returning its root
+    /// and the sum of its values. The first `full` levels of the whole tree
+    /// are complete, so colouring everything below them red leaves every
+    /// path with the same black height.
+    fn build<I>(&mut self, items: &mut I, n: usize, depth: u32, full: u32) -> (NodeId, V)
+    where
+        I: Iterator<Item = (K, V)>,
+    {
+        if n == 0 {
+            return (NIL, V::identity());
+        }
+        let (l, lsum) = self.build(items, n / 2, depth + 1, full);
+        let (k, v) = items.next().unwrap();
+        let val = lsum.combine(&v);
+        let (r, rsum) = self.build(items, n - n / 2 - 1, depth + 1, full);
+        let total = val.combine(&rsum);
+
+        let node = self.alloc(k, val, NIL);
+        {
+            let nv = self.node_mut(node);
+            nv.left = l;
+            nv.right = r;
+            nv.color = if depth < full { Color::Black } else { Color::Red };
+        }
+        for &c in &[l, r] {
+            if c != NIL {
+                self.node_mut(c).parent = node;
+            }
+        }
+        (node, total)
+    }
+
+    /// Get the first key at which the cumulative value equals or exceeds
+    /// the specified value, if such a key exists. This is the same as
+    /// `CumlMap::get_quantile`, but returns a reference to the key rather
+    /// than a copy of it.
+    ///
+    /// # Examples
+    /// ```
+    /// use cuml_map::{CumlMap, CumlTree};
+    ///
+    /// let mut ct: CumlTree<String, u32> = CumlTree::new();
+    /// ct.insert("apple".to_string(), 3);
+    /// ct.insert("banana".to_string(), 2);
+    /// assert_eq!(ct.get_quantile_ref(4).map(|s| s.as_str()), Some("banana"));
+    /// ```
+    pub fn get_quantile_ref(&self, quant: V) -> Option<&K>
+    where
+        V: PartialOrd,
+    {
+        // A value which is incomparable even with itself, such as NaN, is
+        // never reached.
+        quant.partial_cmp(&quant)?;
+        let mut quant = quant;
+        let mut found = None;
+        let mut n = self.root;
+        while let Some(nv) = self.get(n) {
+            // Anything incomparable is treated as though `quant` were less
+            // than it. On equality the left subtree is still searched, since
+            // keys with zero values may leave an earlier key with the same
+            // cumulative value.
+            match quant.partial_cmp(&nv.val).unwrap_or(Ordering::Less) {
+                Ordering::Greater => {
+                    quant = quant.combine_inverse(&nv.val);
+                    n = nv.right;
+                },
+                _ => {
+                    found = Some(&nv.index);
+                    n = nv.left;
+                },
+            }
+        }
+        found
+    }
+
+    fn find(&self, k: &K) -> NodeId {
+        let mut n = self.root;
+        while let Some(nv) = self.get(n) {
+            match k.cmp(&nv.index) {
+                Ordering::Less => n = nv.left,
+                Ordering::Greater => n = nv.right,
+                Ordering::Equal => break,
+            }
+        }
+        n
+    }
+
+    /// Combine `v` into the value at key `k` using `f`, updating the
+    /// subtotals of every node along the way, and creating a new node for
+    /// `k` if none exists. Returns the node holding `k`.
+    fn apply<F>(&mut self, k: K, f: F) -> NodeId
+    where
+        F: Fn(&V) -> V,
+    {
+        let mut n = self.root;
+        let mut p = NIL;
+        let mut left = false;
+        while n != NIL {
+            p = n;
+            let nv = self.node_mut(n);
+            match k.cmp(&nv.index) {
+                Ordering::Less => {
+                    nv.val = f(&nv.val);
+                    n = nv.left;
+                    left = true;
+                },
+                Ordering::Greater => {
+                    n = nv.right;
+                    left = false;
+                },
+                Ordering::Equal => {
+                    nv.val = f(&nv.val);
+                    return n;
+                },
+            }
+        }
+        n = self.alloc(k, f(&V::identity()), p);
+        self.len += 1;
+        if p == NIL {
+            self.root = n;
+        } else if left {
+            self.node_mut(p).left = n;
+        } else {
+            self.node_mut(p).right = n;
+        }
+        self.rb_fix(n);
+        n
+    }
+
+    /// Delete node `n` if pruning is enabled and its value is zero.
+    fn prune(&mut self, n: NodeId) {
+        if self.prune && self.single(n) == V::identity() {
+            self.delete(n);
+        }
+    }
+
+    /// Unlink node `z` from the tree and free it. The single value stored
+    /// at `z` must already have been zeroed out.
+    fn delete(&mut self, z: NodeId) {
+        let mut z = z;
+        if self.node(z).left != NIL && self.node(z).right != NIL {
+            // Move the in-order successor `y` into `z`'s place, and unlink
+            // the successor's old node instead.
+            let mut y = self.node(z).right;
+            while self.node(y).left != NIL {
+                y = self.node(y).left;
+            }
+            let s = self.node(y).val.clone();
+            let mut n = self.node(z).right;
+            while n != y {
+                let nv = self.node_mut(n);
+                nv.val = nv.val.combine_inverse(&s);
+                n = nv.left;
+            }
+            let (zv, yv) = self.node_pair_mut(z, y);
+            mem::swap(&mut zv.index, &mut yv.index);
+            zv.val = zv.val.combine(&s);
+            yv.val = V::identity();
+            z = y;
+        }
+
+        let (x, xp, color) = {
+            let zv = self.node(z);
+            let x = if zv.left != NIL { zv.left } else { zv.right };
+            (x, zv.parent, zv.color)
+        };
+        if x != NIL {
+            self.node_mut(x).parent = xp;
+        }
+        self.replace_child(xp, z, x);
+        if color == Color::Black {
+            self.delete_fix(x, xp);
+        }
+        self.release(z);
+        self.len -= 1;
+        if self.len == 0 {
+            self.nodes.clear();
+            self.free = NIL;
+        }
+    }
+
+    fn delete_fix(&mut self, x: NodeId, xp: NodeId) {
+        let mut x = x;
+        let mut xp = xp;
+        while x != self.root && self.color(x) == Color::Black {
+            if self.node(xp).left == x {
+                let mut w = self.node(xp).right;
+                if self.color(w) == Color::Red {
+                    self.recolor(w, Color::Black);
+                    self.recolor(xp, Color::Red);
+                    self.left_rotate(xp);
+                    w = self.node(xp).right;
+                }
+                let (wl, wr) = (self.node(w).left, self.node(w).right);
+                if self.color(wl) == Color::Black && self.color(wr) == Color::Black {
+                    self.recolor(w, Color::Red);
+                    x = xp;
+                    xp = self.node(xp).parent;
+                } else {
+                    if self.color(wr) == Color::Black {
+                        self.recolor(wl, Color::Black);
+                        self.recolor(w, Color::Red);
+                        self.right_rotate(w);
+                        w = self.node(xp).right;
                     }
+                    let pc = self.color(xp);
+                    self.recolor(w, pc);
+                    self.recolor(xp, Color::Black);
+                    let wr = self.node(w).right;
+                    self.recolor(wr, Color::Black);
+                    self.left_rotate(xp);
+                    x = self.root;
+                }
+            } else {
+                let mut w = self.node(xp).left;
+                if self.color(w) == Color::Red {
+                    self.recolor(w, Color::Black);
+                    self.recolor(xp, Color::Red);
+                    self.right_rotate(xp);
+                    w = self.node(xp).left;
+                }
+                let (wl, wr) = (self.node(w).left, self.node(w).right);
+                if self.color(wl) == Color::Black && self.color(wr) == Color::Black {
+                    self.recolor(w, Color::Red);
+                    x = xp;
+                    xp = self.node(xp).parent;
                 } else {
-                    if gv.left_child_eq(pp) {
-                        unsafe { self.right_rotate(gp) };
-                    } else {
-                        unsafe { self.left_rotate(gp) };
+                    if self.color(wl) == Color::Black {
+                        self.recolor(wr, Color::Black);
+                        self.recolor(w, Color::Red);
+                        self.left_rotate(w);
+                        w = self.node(xp).left;
                     }
-                    gv.recolor(Color::Red);
-                    pv.recolor(Color::Black);
+                    let pc = self.color(xp);
+                    self.recolor(w, pc);
+                    self.recolor(xp, Color::Black);
+                    let wl = self.node(w).left;
+                    self.recolor(wl, Color::Black);
+                    self.right_rotate(xp);
+                    x = self.root;
                 }
             }
-        } else {
-            nv.recolor(Color::Black);
+        }
+        if x != NIL {
+            self.recolor(x, Color::Black);
         }
     }
 
-    unsafe fn left_rotate(&mut self, oldn: NodeRef<K, V>) {
-        let oldnv = oldn.borrow_mut().unwrap();
-        let newn = oldnv.right();
-        let newnv = newn.borrow_mut().unwrap();
-        oldnv.set_right(newnv.left());
-        if let Some(r) = oldnv.right().borrow_mut() {
-            r.set_parent(oldn);
+    fn left_rotate(&mut self, oldn: NodeId) {
+        let (newn, p) = (self.node(oldn).right, self.node(oldn).parent);
+        let inner = self.node(newn).left;
+        self.node_mut(oldn).right = inner;
+        if inner != NIL {
+            self.node_mut(inner).parent = oldn;
         }
-        newnv.set_val(newnv.val() + oldnv.val());
-        if let Some(p) = oldnv.parent().borrow_mut() {
-            p.swap_child(oldn, newn);
-            newnv.set_parent(oldnv.parent());
-        } else {
-            self.root = newn;
-            newnv.set_parent(NodeRef::null());
+        let (oldnv, newnv) = self.node_pair_mut(oldn, newn);
+        newnv.val = newnv.val.combine(&oldnv.val);
+        newnv.parent = p;
+        newnv.left = oldn;
+        oldnv.parent = newn;
+        self.replace_child(p, oldn, newn);
+    }
+
+    fn right_rotate(&mut self, oldn: NodeId) {
+        let (newn, p) = (self.node(oldn).left, self.node(oldn).parent);
+        let inner = self.node(newn).right;
+        self.node_mut(oldn).left = inner;
+        if inner != NIL {
+            self.node_mut(inner).parent = oldn;
         }
-        newnv.set_left(oldn);
-        oldnv.set_parent(newn);
+        let (oldnv, newnv) = self.node_pair_mut(oldn, newn);
+        oldnv.val = oldnv.val.combine_inverse(&newnv.val);
+        newnv.parent = p;
+        newnv.right = oldn;
+        oldnv.parent = newn;
+        self.replace_child(p, oldn, newn);
     }
+}
 
-    unsafe fn right_rotate(&mut self, oldn: NodeRef<K, V>) {
-        let oldnv = oldn.borrow_mut().unwrap();
-        let newn = oldnv.left();
-        let newnv = newn.borrow_mut().unwrap();
-        oldnv.set_left(newnv.right());
-        if let Some(l) = oldnv.left().borrow_mut() {
-            l.set_parent(oldn);
+#[cfg(test)]
+impl<K: Ord, V> CumlTree<K, V> {
+    /// Panic if the tree violates any of the red-black invariants, or if
+    /// its links, length or free list are inconsistent.
+    pub fn check_invariants(&self) {
+        fn check<K: Ord, V>(t: &CumlTree<K, V>, n: NodeId, p: NodeId, count: &mut usize) -> usize {
+            match t.get(n) {
+                None => 1,
+                Some(nv) => {
+                    *count += 1;
+                    assert!(nv.parent == p, "broken parent link");
+                    if nv.color == Color::Red {
+                        assert!(t.color(nv.left) == Color::Black, "red node with red child");
+                        assert!(t.color(nv.right) == Color::Black, "red node with red child");
+                    }
+                    if let Some(l) = t.get(nv.left) {
+                        assert!(l.index < nv.index, "keys out of order");
+                    }
+                    if let Some(r) = t.get(nv.right) {
+                        assert!(r.index > nv.index, "keys out of order");
+                    }
+                    let lh = check(t, nv.left, n, count);
+                    let rh = check(t, nv.right, n, count);
+                    assert_eq!(lh, rh, "unequal black heights");
+                    lh + if nv.color == Color::Black { 1 } else { 0 }
+                },
+            }
         }
-        oldnv.set_val(oldnv.val() - newnv.val());
-        if let Some(p) = oldnv.parent().borrow_mut() {
-            p.swap_child(oldn, newn);
-            newnv.set_parent(oldnv.parent());
-        } else {
-            self.root = newn;
-            newnv.set_parent(NodeRef::null());
+
+        assert!(self.color(self.root) == Color::Black, "red root");
+        let mut count = 0;
+        check(self, self.root, NIL, &mut count);
+        assert_eq!(count, self.len, "wrong length");
+
+        let mut vacant = 0;
+        let mut f = self.free;
+        while f != NIL {
+            match self.nodes[f as usize] {
+                Slot::Vacant(next) => f = next,
+                Slot::Occupied(_) => panic!("free list links to an occupied slot"),
+            }
+            vacant += 1;
         }
-        newnv.set_right(oldn);
-        oldnv.set_parent(newn);
+        assert_eq!(count + vacant, self.nodes.len(), "leaked slots");
+    }
+
+    /// Panic if `check_invariants` does, or if any node's augmented value is
+    /// not the total of its left subtree combined with its own value, taking
+    /// the values at each key from `expected`, in key order. The tree alone
+    /// cannot reveal a wrong augmented value, since it only records each
+    /// node's own value as the difference of two of them.
+    pub fn check_sums<'a, I>(&self, expected: I)
+    where
+        K: 'a,
+        V: Group + 'a,
+        I: IntoIterator<Item = (&'a K, &'a V)>,
+    {
+        fn sums<'a, K, V, I>(t: &CumlTree<K, V>, n: NodeId, exp: &mut std::iter::Peekable<I>) -> V
+        where
+            K: Ord + 'a,
+            V: Group + 'a,
+            I: Iterator<Item = (&'a K, &'a V)>,
+        {
+            let nv = match t.get(n) {
+                None => return V::identity(),
+                Some(nv) => nv,
+            };
+            let left = sums(t, nv.left, exp);
+            let own = match exp.peek() {
+                Some(&(k, _)) if *k < nv.index => panic!("expected key missing from the tree"),
+                Some(&(k, v)) if *k == nv.index => {
+                    exp.next();
+                    v.clone()
+                }
+                _ => V::identity(),
+            };
+            let below = left.combine(&own);
+            assert!(nv.val == below, "wrong augmented sum");
+            below.combine(&sums(t, nv.right, exp))
+        }
+
+        self.check_invariants();
+        let mut exp = expected
+            .into_iter()
+            .filter(|e| *e.1 != V::identity())
+            .peekable();
+        sums(self, self.root, &mut exp);
+        assert!(exp.next().is_none(), "expected key missing from the tree");
     }
 }
 
 impl<K, V> CumlMap for CumlTree<K, V>
 where
-    K: Add<Output = K> + Sub<Output = K> + Zero + Clone + Ord,
-    V: Add<Output = V> + Sub<Output = V> + Zero + Clone + Ord,
+    K: Clone + Ord,
+    V: Group,
 {
     type Key = K;
     type Value = V;
 
     fn insert(&mut self, k: Self::Key, v: Self::Value) {
+        let n = self.apply(k, |x| x.combine(&v));
+        self.prune(n);
+    }
+
+    fn set(&mut self, k: Self::Key, v: Self::Value) {
+        let old = match self.find(&k) {
+            NIL => V::identity(),
+            n => self.single(n),
+        };
+        if v != old {
+            let n = self.apply(k, |x| x.combine_inverse(&old).combine(&v));
+            self.prune(n);
+        }
+    }
+
+    fn remove(&mut self, k: Self::Key) -> Self::Value {
+        let n = self.find(&k);
+        if n == NIL {
+            return V::identity();
+        }
+        let old = self.single(n);
+        self.apply(k, |x| x.combine_inverse(&old));
+        self.delete(n);
+        old
+    }
+
+    fn get_cuml(&self, k: Self::Key) -> Self::Value {
+        self.sum_while(self.root, |i| i <= &k)
+    }
+
+    fn get_single(&self, k: Self::Key) -> Self::Value {
+        match self.find(&k) {
+            NIL => V::identity(),
+            n => self.single(n),
+        }
+    }
+
+    fn get_range<R>(&self, range: R) -> Self::Value
+    where
+        R: RangeBounds<Self::Key>,
+    {
+        let (start, end) = (range.start_bound(), range.end_bound());
         let mut n = self.root;
-        let mut p = NodeRef::null();
-        while let Some(nv) = n.borrow_mut() {
-            p = n;
-            match k.cmp(&nv.index()) {
-                Ordering::Less => {
-                    nv.set_val(nv.val() + v.clone());
-                    n = nv.left();
-                },
-                Ordering::Greater => {
-                    n = nv.right();
-                },
-                Ordering::Equal => {
-                    nv.set_val(nv.val() + v.clone());
-                    return
-                },
+        while let Some(nv) = self.get(n) {
+            if !before_end(end, &nv.index) {
+                n = nv.left;
+            } else if before_start(start, &nv.index) {
+                n = nv.right;
+            } else {
+                // The range splits at this node, so the answer is everything
+                // in the left subtree from the start of the range onward, plus
+                // everything in the right subtree up to the end of the range.
+                let before = self.sum_while(nv.left, |k| before_start(start, k));
+                let after = self.sum_while(nv.right, |k| before_end(end, k));
+                return nv.val.combine_inverse(&before).combine(&after);
             }
         }
-        n = NodeRef::new(k.clone(), v.clone(), p);
-        if let Some(pv) = p.borrow_mut() {
-            match k.cmp(&pv.index()) {
-                Ordering::Less => pv.set_left(n),
-                Ordering::Greater => pv.set_right(n),
-                Ordering::Equal => panic!("Cosmic-ray error"),
-            }
+        V::identity()
+    }
+
+    fn get_quantile(&self, quant: Self::Value) -> Option<Self::Key>
+    where
+        V: PartialOrd,
+    {
+        self.get_quantile_ref(quant).cloned()
+    }
+}
+
+impl<K, V> FromIterator<(K, V)> for CumlTree<K, V>
+where
+    K: Ord,
+    V: Group,
+{
+    /// Collect `(key, value)` pairs in any order into a `CumlTree`, by
+    /// sorting them and handing them to `from_sorted`.
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut items: Vec<(K, V)> = iter.into_iter().collect();
+        items.sort_by(|a, b| a.0.cmp(&b.0));
+        Self::from_sorted(items)
+    }
+}
+
+impl<K, V> Default for CumlTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> CumlTree<K, V>
+where
+    K: Ord,
+    V: Group,
+{
+    /// Iterate over the entries with non-zero values, in key order.
+    fn nonzero(&self) -> impl Iterator<Item = (&K, V)> + '_ {
+        let zero = V::identity();
+        self.iter().filter(move |e| e.1 != zero)
+    }
+}
+
+impl<K, V> fmt::Debug for CumlTree<K, V>
+where
+    K: Ord + fmt::Debug,
+    V: Group + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.nonzero()).finish()
+    }
+}
+
+impl<K: Ord, V: Group> PartialEq for CumlTree<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.nonzero().eq(other.nonzero())
+    }
+}
+
+impl<K: Ord, V: Group + Eq> Eq for CumlTree<K, V> {}
+
+impl<K, V> Hash for CumlTree<K, V>
+where
+    K: Ord + Hash,
+    V: Group + Hash,
+{
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_entries(self.nonzero(), state)
+    }
+}
+
+/// An iterator over the `(key, value)` pairs of a `CumlTree`.
+pub struct CumlTreeIter<'a, K: 'a, V: 'a> {
+    tree: &'a CumlTree<K, V>,
+    front: NodeId,
+    back: NodeId,
+}
+
+impl<'a, K, V> CumlTreeIter<'a, K, V>
+where
+    K: Ord,
+    V: Group,
+{
+    /// Yield cumulative values rather than single values.
+    pub fn cumulative(self) -> Cumulative<Self, V> {
+        let tree = self.tree;
+        let (front, back) = match (tree.get(self.front), tree.get(self.back)) {
+            (Some(f), Some(b)) => (
+                tree.sum_while(tree.root, |k| k < &f.index),
+                tree.sum_while(tree.root, |k| k <= &b.index),
+            ),
+            _ => (V::identity(), V::identity()),
+        };
+        Cumulative::new(self, front, back)
+    }
+}
+
+impl<'a, K, V> Iterator for CumlTreeIter<'a, K, V>
+where
+    K: Ord,
+    V: Group,
+{
+    type Item = (&'a K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let tree = self.tree;
+        let n = self.front;
+        let nv = tree.get(n)?;
+        if n == self.back {
+            self.front = NIL;
+            self.back = NIL;
         } else {
-            self.root = n;
+            self.front = tree.successor(n);
         }
-        self.rb_fix(n);
+        Some((&nv.index, tree.single(n)))
     }
+}
 
-    fn get_cuml(&self, k: Self::Key) -> Self::Value {
-        match self.root.borrow_mut() {
-            None => V::zero(),
-            Some(r) => r.get_cuml(k, V::zero()),
+impl<'a, K, V> DoubleEndedIterator for CumlTreeIter<'a, K, V>
+where
+    K: Ord,
+    V: Group,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let tree = self.tree;
+        let n = self.back;
+        let nv = tree.get(n)?;
+        if n == self.front {
+            self.front = NIL;
+            self.back = NIL;
+        } else {
+            self.back = tree.predecessor(n);
         }
+        Some((&nv.index, tree.single(n)))
     }
+}
 
-    fn get_single(&self, k: Self::Key) -> Self::Value {
-        match self.root.borrow_mut() {
-            None => V::zero(),
-            Some(r) => r.get_single(k),
+/*****************************************************************************
+ * Serialization, as a list of entries in key order
+ *****************************************************************************/
+
+#[cfg(feature = "serde")]
+mod serialize {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(rename = "CumlTree")]
+    struct Repr<K, V> {
+        pruning: bool,
+        entries: Vec<(K, V)>,
+    }
+
+    impl<K, V> Serialize for CumlTree<K, V>
+    where
+        K: Ord + Serialize,
+        V: Group + Serialize,
+    {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            Repr {
+                pruning: self.prune,
+                entries: self.iter().collect(),
+            }.serialize(serializer)
         }
     }
 
-    fn get_quantile(&self, quant: Self::Value) -> Option<Self::Key> {
-        match self.root.borrow_mut() {
-            None => None,
-            Some(r) => r.get_quantile(quant),
+    impl<'de, K, V> Deserialize<'de> for CumlTree<K, V>
+    where
+        K: Ord + Deserialize<'de>,
+        V: Group + Deserialize<'de>,
+    {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let repr: Repr<K, V> = Repr::deserialize(deserializer)?;
+            if repr.entries.windows(2).any(|w| w[0].0 >= w[1].0) {
+                return Err(D::Error::custom("keys are not in strictly increasing order"));
+            }
+            if repr.pruning && repr.entries.iter().any(|e| e.1 == V::identity()) {
+                return Err(D::Error::custom("pruning tree holds a key with a zero value"));
+            }
+            let mut tree = CumlTree::from_sorted(repr.entries);
+            tree.prune = repr.pruning;
+            Ok(tree)
         }
     }
 }
@@ -0,0 +1,570 @@
+extern crate num_traits;
+use num_traits::{Bounded, PrimInt};
+use std::cmp::Ordering;
+use std::convert::Infallible;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::RangeBounds;
+
+use algebra::*;
+use bix::*;
+use cmap::*;
+
+/*****************************************************************************
+ * Operations for segment trees
+ *****************************************************************************/
+
+/// An associative operation on values of type `V`, with an identity, by
+/// which a `SegmentTree` combines the values over a range of keys. Unlike
+/// the `Group` operations of the other maps, these need not be invertible or
+/// commutative, so operations such as the minimum or greatest common divisor
+/// can be used.
+///
+/// An operation may also support adding a `Delta` to every value in a range,
+/// through `SegmentTree::add_range`, so long as the combination of the
+/// updated values can be found from the combination of the old values.
+/// Operations which do not support this set `Delta` to `Infallible`, which
+/// can never be passed to `add_range`.
+pub trait SegmentOp<V> {
+    /// The type of the values which `add_range` adds to each key.
+    type Delta: Clone;
+
+    /// The identity element, which leaves any value unchanged when combined
+    /// with it.
+    fn identity() -> V;
+
+    /// Combine the values of two neighbouring ranges, `a` before `b`.
+    fn combine(a: &V, b: &V) -> V;
+
+    /// Given the combination `agg` of the values at `len` keys, get the
+    /// combination of those values after adding `delta` to each of them.
+    fn add(agg: &V, delta: &Self::Delta, len: usize) -> V;
+
+    /// Get the delta which adds `first` and then `second`.
+    fn compose(first: &Self::Delta, second: &Self::Delta) -> Self::Delta;
+}
+
+/// Combine ranges of values by summing them, as in a `FenwickTree`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sum;
+
+impl<V: Monoid> SegmentOp<V> for Sum {
+    type Delta = V;
+
+    fn identity() -> V {
+        V::identity()
+    }
+
+    fn combine(a: &V, b: &V) -> V {
+        a.combine(b)
+    }
+
+    fn add(agg: &V, delta: &V, len: usize) -> V {
+        agg.combine(&times(delta, len))
+    }
+
+    fn compose(first: &V, second: &V) -> V {
+        first.combine(second)
+    }
+}
+
+/// Combine ranges of values by taking their minimum. The identity is the
+/// greatest value of the type, and adding to a range shifts its minimum.
+/// Keys holding the identity, such as those never set or since removed, hold
+/// no value to shift, so `add_range` leaves them unchanged.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Min;
+
+impl<V: Monoid + PartialOrd + Bounded> SegmentOp<V> for Min {
+    type Delta = V;
+
+    fn identity() -> V {
+        V::max_value()
+    }
+
+    fn combine(a: &V, b: &V) -> V {
+        if b < a { b.clone() } else { a.clone() }
+    }
+
+    fn add(agg: &V, delta: &V, _len: usize) -> V {
+        if *agg == Self::identity() {
+            agg.clone()
+        } else {
+            agg.combine(delta)
+        }
+    }
+
+    fn compose(first: &V, second: &V) -> V {
+        first.combine(second)
+    }
+}
+
+/// Combine ranges of values by taking their maximum. The identity is the
+/// least value of the type, and adding to a range shifts its maximum. As
+/// for `Min`, `add_range` leaves keys holding the identity unchanged.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Max;
+
+impl<V: Monoid + PartialOrd + Bounded> SegmentOp<V> for Max {
+    type Delta = V;
+
+    fn identity() -> V {
+        V::min_value()
+    }
+
+    fn combine(a: &V, b: &V) -> V {
+        if b > a { b.clone() } else { a.clone() }
+    }
+
+    fn add(agg: &V, delta: &V, _len: usize) -> V {
+        if *agg == Self::identity() {
+            agg.clone()
+        } else {
+            agg.combine(delta)
+        }
+    }
+
+    fn compose(first: &V, second: &V) -> V {
+        first.combine(second)
+    }
+}
+
+/// Combine ranges of integers by taking their greatest common divisor,
+/// which is never negative. The identity is zero. This does not support
+/// `add_range`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Gcd;
+
+impl<V: PrimInt> SegmentOp<V> for Gcd {
+    type Delta = Infallible;
+
+    fn identity() -> V {
+        V::zero()
+    }
+
+    fn combine(a: &V, b: &V) -> V {
+        let (mut a, mut b) = (*a, *b);
+        while b != V::zero() {
+            let r = a % b;
+            a = b;
+            b = r;
+        }
+        if a < V::zero() { V::zero() - a } else { a }
+    }
+
+    fn add(_agg: &V, delta: &Infallible, _len: usize) -> V {
+        match *delta {}
+    }
+
+    fn compose(first: &Infallible, _second: &Infallible) -> Infallible {
+        match *first {}
+    }
+}
+
+/*****************************************************************************
+ * Segment tree with lazy range updates
+ *****************************************************************************/
+
+/// An update waiting to be applied to every key below a node.
+enum Tag<V, D> {
+    Assign(V),
+    Add(D),
+}
+
+impl<V: Clone, D: Clone> Clone for Tag<V, D> {
+    fn clone(&self) -> Self {
+        match self {
+            Tag::Assign(v) => Tag::Assign(v.clone()),
+            Tag::Add(d) => Tag::Add(d.clone()),
+        }
+    }
+}
+
+/// A segment tree over the keys `0..capacity`, which combines the values
+/// over any range of keys with an associative operation `Op` (by default,
+/// `Sum`), in `O(log n)` time. Since the operation need not be invertible,
+/// this can answer queries such as range minimum, maximum and greatest
+/// common divisor, which a `FenwickTree` cannot.
+///
+/// Every key in a range can be set to one value with `assign_range`, or
+/// have a value added to it with `add_range` if `Op` supports that, also in
+/// `O(log n)` time. These updates are recorded lazily on the nodes covering
+/// the range, and only pushed down to their children when a later update
+/// needs to pass through them.
+///
+/// In the `CumlMap` implementation, `insert` combines a value into the one
+/// already at a key using `Op`, and the cumulative value at a key is the
+/// combination of the values at every key up to and including it. For
+/// operations other than `Sum`, `get_quantile` therefore finds the first key
+/// at which, say, the running maximum reaches the value given; `max_right`
+/// generalizes this to any monotonic predicate.
+///
+/// # Examples
+/// ```
+/// use cuml_map::{CumlMap, Max, Min, SegmentTree};
+///
+/// let mut lows: SegmentTree<i32, Min> = SegmentTree::from_slice(&[5, 3, 8, 6, 2, 7]);
+/// assert_eq!(lows.get_range(..3), 3);
+/// assert_eq!(lows.get_range(2..), 2);
+/// lows.add_range(1..4, 10);
+/// assert_eq!(lows.get_range(..4), 5);
+///
+/// let mut highs: SegmentTree<i32, Max> = SegmentTree::with_capacity(6);
+/// highs.assign_range(.., 1);
+/// highs.insert(4, 9);
+/// assert_eq!(highs.get_cuml(3), 1);
+/// assert_eq!(highs.get_quantile(5), Some(4));
+/// ```
+pub struct SegmentTree<V, Op: SegmentOp<V> = Sum> {
+    capacity: usize,
+    size: usize,
+    data: Vec<V>,
+    tags: Vec<Option<Tag<V, Op::Delta>>>,
+    op: PhantomData<Op>,
+}
+
+impl<V, Op> SegmentTree<V, Op>
+where
+    V: Clone,
+    Op: SegmentOp<V>,
+{
+    /// Create a `SegmentTree` object with a fixed capacity, with every value
+    /// set to the identity of `Op`.
+    pub fn with_capacity(c: usize) -> Self {
+        let size = c.next_power_of_two();
+        SegmentTree {
+            capacity: c,
+            size,
+            data: vec![Op::identity(); 2 * size],
+            tags: vec![None; size],
+            op: PhantomData,
+        }
+    }
+
+    /// Create a `SegmentTree` object from a slice of values, with the `i`th
+    /// value stored at key `i`, in linear time.
+    pub fn from_slice(vals: &[V]) -> Self {
+        let mut tree = Self::with_capacity(vals.len());
+        tree.data[tree.size..tree.size + vals.len()].clone_from_slice(vals);
+        for n in (1..tree.size).rev() {
+            tree.data[n] = Op::combine(&tree.data[2 * n], &tree.data[2 * n + 1]);
+        }
+        tree
+    }
+
+    /// Get the number of keys covered by the tree.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Set the value at every key within `range` to `val`.
+    pub fn assign_range<R>(&mut self, range: R, val: V)
+    where
+        R: RangeBounds<usize>,
+    {
+        let (lo, hi) = clamp_range(range, 0, self.capacity);
+        self.update(1, 0, self.size, lo, hi, &Tag::Assign(val));
+    }
+
+    /// Add `delta` to the value at every key within `range`.
+    pub fn add_range<R>(&mut self, range: R, delta: Op::Delta)
+    where
+        R: RangeBounds<usize>,
+    {
+        let (lo, hi) = clamp_range(range, 0, self.capacity);
+        self.update(1, 0, self.size, lo, hi, &Tag::Add(delta));
+    }
+
+    /// Find the furthest key `hi` such that `pred` holds for the combination
+    /// of the values at keys in `[lo, hi)`, in `O(log n)` time. If `pred`
+    /// holds for every range starting at `lo`, this is the capacity.
+    ///
+    /// `pred` must hold for the identity, and be monotonic: once it fails
+    /// for some range starting at `lo`, it must fail for every longer one.
+    /// If it fails for the identity, the result is `lo`.
+    ///
+    /// # Panics
+    /// Panics if `lo` is beyond the tree's capacity.
+    ///
+    /// # Examples
+    /// ```
+    /// use cuml_map::SegmentTree;
+    ///
+    /// let st: SegmentTree<u32> = SegmentTree::from_slice(&[2, 5, 1, 4, 3]);
+    /// // The longest run from key 1 whose sum is at most 7.
+    /// assert_eq!(st.max_right(1, |&s| s <= 7), 3);
+    /// assert_eq!(st.max_right(3, |&s| s <= 7), 5);
+    /// ```
+    pub fn max_right<P>(&self, lo: usize, pred: P) -> usize
+    where
+        P: Fn(&V) -> bool,
+    {
+        assert!(lo <= self.capacity);
+        let mut acc = Op::identity();
+        if !pred(&acc) {
+            return lo;
+        }
+        self.search(1, 0, self.size, lo, &pred, &mut acc, None)
+            .map_or(self.capacity, |hi| hi.min(self.capacity))
+    }
+
+    /// Get the combination of `n` copies of `v`.
+    fn repeat(v: &V, n: usize) -> V {
+        let mut n = n;
+        let mut acc = Op::identity();
+        let mut pow = v.clone();
+        while n > 0 {
+            if n & 1 == 1 {
+                acc = Op::combine(&acc, &pow);
+            }
+            n >>= 1;
+            if n > 0 {
+                pow = Op::combine(&pow, &pow);
+            }
+        }
+        acc
+    }
+
+    /// Get the combination `agg` of the values at `len` keys, after applying
+    /// `tag` to each of them.
+    fn tagged(agg: &V, tag: &Tag<V, Op::Delta>, len: usize) -> V {
+        match tag {
+            Tag::Assign(v) => Self::repeat(v, len),
+            Tag::Add(d) => Op::add(agg, d, len),
+        }
+    }
+
+    /// Get the single tag which applies `first` and then `second`.
+    fn then(
+        first: Option<&Tag<V, Op::Delta>>,
+        second: Option<&Tag<V, Op::Delta>>,
+    ) -> Option<Tag<V, Op::Delta>> {
+        match (first, second) {
+            (first, None) => first.cloned(),
+            (None, Some(second)) | (Some(_), Some(second @ Tag::Assign(_))) => Some(second.clone()),
+            (Some(Tag::Assign(v)), Some(Tag::Add(d))) => Some(Tag::Assign(Op::add(v, d, 1))),
+            (Some(Tag::Add(a)), Some(Tag::Add(d))) => Some(Tag::Add(Op::compose(a, d))),
+        }
+    }
+
+    /// Apply `tag` to node `n`, which covers `len` keys.
+    fn apply(&mut self, n: usize, len: usize, tag: &Tag<V, Op::Delta>) {
+        self.data[n] = Self::tagged(&self.data[n], tag, len);
+        if n < self.size {
+            self.tags[n] = Self::then(self.tags[n].as_ref(), Some(tag));
+        }
+    }
+
+    /// Push the tag on node `n`, which covers `len` keys, down to its
+    /// children.
+    fn push(&mut self, n: usize, len: usize) {
+        if let Some(tag) = self.tags[n].take() {
+            self.apply(2 * n, len / 2, &tag);
+            self.apply(2 * n + 1, len / 2, &tag);
+        }
+    }
+
+    /// Apply `tag` to every key in `[lo, hi)` below node `n`, which covers
+    /// the keys `[nlo, nhi)`.
+    fn update(
+        &mut self,
+        n: usize,
+        nlo: usize,
+        nhi: usize,
+        lo: usize,
+        hi: usize,
+        tag: &Tag<V, Op::Delta>,
+    ) {
+        if hi <= nlo || nhi <= lo {
+            return;
+        }
+        if lo <= nlo && nhi <= hi {
+            self.apply(n, nhi - nlo, tag);
+            return;
+        }
+        self.push(n, nhi - nlo);
+        let mid = nlo + (nhi - nlo) / 2;
+        self.update(2 * n, nlo, mid, lo, hi, tag);
+        self.update(2 * n + 1, mid, nhi, lo, hi, tag);
+        self.data[n] = Op::combine(&self.data[2 * n], &self.data[2 * n + 1]);
+    }
+
+    /// Get the value of node `n`, which covers `len` keys, once `pending`
+    /// has been applied to it.
+    fn value(&self, n: usize, len: usize, pending: Option<&Tag<V, Op::Delta>>) -> V {
+        match pending {
+            None => self.data[n].clone(),
+            Some(tag) => Self::tagged(&self.data[n], tag, len),
+        }
+    }
+
+    /// Get the tag pending for the children of node `n`, given the tag
+    /// `pending` from its ancestors, which is newer than its own.
+    fn child_tag(
+        &self,
+        n: usize,
+        pending: Option<&Tag<V, Op::Delta>>,
+    ) -> Option<Tag<V, Op::Delta>> {
+        if n < self.size {
+            Self::then(self.tags[n].as_ref(), pending)
+        } else {
+            None
+        }
+    }
+
+    /// Combine the values at every key in `[lo, hi)` below node `n`, which
+    /// covers the keys `[nlo, nhi)`, without pushing any tags down.
+    fn query(
+        &self,
+        n: usize,
+        nlo: usize,
+        nhi: usize,
+        lo: usize,
+        hi: usize,
+        pending: Option<&Tag<V, Op::Delta>>,
+    ) -> V {
+        if hi <= nlo || nhi <= lo {
+            return Op::identity();
+        }
+        if lo <= nlo && nhi <= hi {
+            return self.value(n, nhi - nlo, pending);
+        }
+        let pending = self.child_tag(n, pending);
+        let mid = nlo + (nhi - nlo) / 2;
+        Op::combine(
+            &self.query(2 * n, nlo, mid, lo, hi, pending.as_ref()),
+            &self.query(2 * n + 1, mid, nhi, lo, hi, pending.as_ref()),
+        )
+    }
+
+    /// Extend `acc`, the combination of the values from `lo` up to `nlo`,
+    /// through the keys below node `n`, which covers `[nlo, nhi)`, for as
+    /// long as `pred` holds. Returns the first key at which it fails, if any.
+    #[allow(clippy::too_many_arguments)]
+    fn search<P>(
+        &self,
+        n: usize,
+        nlo: usize,
+        nhi: usize,
+        lo: usize,
+        pred: &P,
+        acc: &mut V,
+        pending: Option<&Tag<V, Op::Delta>>,
+    ) -> Option<usize>
+    where
+        P: Fn(&V) -> bool,
+    {
+        if nhi <= lo {
+            return None;
+        }
+        if lo <= nlo {
+            let next = Op::combine(acc, &self.value(n, nhi - nlo, pending));
+            if pred(&next) {
+                *acc = next;
+                return None;
+            }
+            if nhi - nlo == 1 {
+                return Some(nlo);
+            }
+        }
+        let pending = self.child_tag(n, pending);
+        let mid = nlo + (nhi - nlo) / 2;
+        self.search(2 * n, nlo, mid, lo, pred, acc, pending.as_ref())
+            .or_else(|| self.search(2 * n + 1, mid, nhi, lo, pred, acc, pending.as_ref()))
+    }
+}
+
+impl<V, Op> CumlMap for SegmentTree<V, Op>
+where
+    V: Clone,
+    Op: SegmentOp<V>,
+{
+    type Key = usize;
+    type Value = V;
+
+    fn insert(&mut self, key: Self::Key, val: Self::Value) {
+        let old = self.get_single(key);
+        self.set(key, Op::combine(&old, &val));
+    }
+
+    fn set(&mut self, key: Self::Key, val: Self::Value) {
+        assert!(key < self.capacity);
+        self.assign_range(key..=key, val);
+    }
+
+    fn remove(&mut self, key: Self::Key) -> Self::Value {
+        if key >= self.capacity {
+            return Op::identity();
+        }
+        let old = self.get_single(key);
+        self.set(key, Op::identity());
+        old
+    }
+
+    fn get_cuml(&self, key: Self::Key) -> Self::Value {
+        self.get_range(..=key)
+    }
+
+    fn get_single(&self, key: Self::Key) -> Self::Value {
+        self.get_range(key..=key)
+    }
+
+    fn get_range<R>(&self, range: R) -> Self::Value
+    where
+        R: RangeBounds<Self::Key>,
+    {
+        let (lo, hi) = clamp_range(range, 0, self.capacity);
+        self.query(1, 0, self.size, lo, hi, None)
+    }
+
+    fn get_quantile(&self, quant: Self::Value) -> Option<Self::Key>
+    where
+        V: PartialOrd,
+    {
+        quant.partial_cmp(&quant)?;
+        let key = self.max_right(0, |c| c.partial_cmp(&quant) == Some(Ordering::Less));
+        if key < self.capacity {
+            Some(key)
+        } else {
+            None
+        }
+    }
+}
+
+impl<V, Op> Clone for SegmentTree<V, Op>
+where
+    V: Clone,
+    Op: SegmentOp<V>,
+{
+    fn clone(&self) -> Self {
+        SegmentTree {
+            capacity: self.capacity,
+            size: self.size,
+            data: self.data.clone(),
+            tags: self.tags.clone(),
+            op: PhantomData,
+        }
+    }
+}
+
+impl<V, Op> Default for SegmentTree<V, Op>
+where
+    V: Clone,
+    Op: SegmentOp<V>,
+{
+    /// Create a `SegmentTree` with zero capacity.
+    fn default() -> Self {
+        Self::with_capacity(0)
+    }
+}
+
+impl<V, Op> fmt::Debug for SegmentTree<V, Op>
+where
+    V: Clone + fmt::Debug,
+    Op: SegmentOp<V>,
+{
+    /// Print the value at every key, in key order.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries((0..self.capacity).map(|k| self.get_single(k))).finish()
+    }
+}
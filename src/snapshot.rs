@@ -0,0 +1,294 @@
+use std::cmp;
+use std::error;
+use std::fmt;
+use std::io::{self, Write};
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::RangeBounds;
+
+use algebra::*;
+use bix::*;
+
+/*****************************************************************************
+ * Binary snapshots of Fenwick trees
+ *****************************************************************************/
+
+// A snapshot is a 32 byte header followed by the tree's nodes, with every
+// integer stored little-endian:
+//
+//   offset  size  field
+//        0     8  magic number, "CUMLFWT\0"
+//        8     4  format version, currently 1
+//       12     4  value type tag, `SnapshotValue::TAG`
+//       16     8  capacity, the number of nodes
+//       24     8  FNV-1a hash of the node bytes which follow
+//       32         `capacity` nodes of `SnapshotValue::SIZE` bytes each
+
+const MAGIC: [u8; 8] = *b"CUMLFWT\0";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = 32;
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// A value type with a fixed-size binary encoding, which may be stored in a
+/// `FenwickTree` snapshot. Implementations are provided for the primitive
+/// numeric types of fixed width.
+pub trait SnapshotValue: Group {
+    /// A number identifying this type in snapshot headers, which must be
+    /// unique among the types stored in snapshots.
+    const TAG: u32;
+
+    /// The number of bytes in the encoding of each value.
+    const SIZE: usize;
+
+    /// Write the little-endian encoding of `self` into `out`, which is
+    /// exactly `SIZE` bytes long.
+    fn encode(&self, out: &mut [u8]);
+
+    /// Read a value from its little-endian encoding in `bytes`, which is
+    /// exactly `SIZE` bytes long.
+    fn decode(bytes: &[u8]) -> Self;
+}
+
+macro_rules! impl_snapshot_value {
+    ($($t:ty => $tag:expr),*) => {
+        $(
+            impl SnapshotValue for $t {
+                const TAG: u32 = $tag;
+                const SIZE: usize = mem::size_of::<$t>();
+
+                fn encode(&self, out: &mut [u8]) {
+                    out.copy_from_slice(&self.to_le_bytes());
+                }
+
+                fn decode(bytes: &[u8]) -> Self {
+                    let mut buf = [0; mem::size_of::<$t>()];
+                    buf.copy_from_slice(bytes);
+                    <$t>::from_le_bytes(buf)
+                }
+            }
+        )*
+    };
+}
+
+impl_snapshot_value!(i8 => 1, i16 => 2, i32 => 3, i64 => 4, i128 => 5);
+impl_snapshot_value!(u8 => 6, u16 => 7, u32 => 8, u64 => 9, u128 => 10);
+impl_snapshot_value!(f32 => 11, f64 => 12);
+
+/// The ways in which a snapshot can fail to load.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// The data does not begin with the snapshot magic number.
+    BadMagic,
+    /// The snapshot was written in a format version this crate cannot read.
+    UnsupportedVersion(u32),
+    /// The snapshot holds values of a different type than was asked for.
+    WrongValueType {
+        /// The tag of the type asked for.
+        expected: u32,
+        /// The tag recorded in the snapshot.
+        found: u32,
+    },
+    /// The data is not the length that its header says it should be.
+    BadLength,
+    /// The nodes do not match the checksum recorded in the header.
+    BadChecksum,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SnapshotError::BadMagic => write!(f, "not a Fenwick tree snapshot"),
+            SnapshotError::UnsupportedVersion(v) => {
+                write!(f, "unsupported snapshot version {}", v)
+            },
+            SnapshotError::WrongValueType { expected, found } => write!(
+                f,
+                "snapshot holds values of type tag {}, not {}",
+                found, expected
+            ),
+            SnapshotError::BadLength => write!(f, "snapshot has the wrong length"),
+            SnapshotError::BadChecksum => write!(f, "snapshot checksum does not match"),
+        }
+    }
+}
+
+impl error::Error for SnapshotError {}
+
+/// Incrementally compute a 64-bit FNV-1a hash.
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |h, &b| (h ^ u64::from(b)).wrapping_mul(0x100_0000_01b3))
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(&bytes[at..at + 4]);
+    u32::from_le_bytes(buf)
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(&bytes[at..at + 8]);
+    u64::from_le_bytes(buf)
+}
+
+impl<V> FenwickTree<V>
+where
+    V: SnapshotValue,
+{
+    /// Write a binary snapshot of the tree, which may later be read back
+    /// with `FenwickTreeView`. Snapshots are independent of the platform's
+    /// byte order, and begin with a header identifying the format version
+    /// and value type, and holding a checksum of the contents.
+    ///
+    /// The tree is written in chunks, so there is no need to wrap `out` in
+    /// a buffered writer.
+    pub fn write_snapshot<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        const CHUNK: usize = 1 << 16;
+        let mut buf = vec![0; V::SIZE * cmp::max(1, CHUNK / V::SIZE)];
+
+        let mut hash = FNV_OFFSET;
+        for nodes in self.data.chunks(buf.len() / V::SIZE) {
+            let len = Self::encode_chunk(nodes, &mut buf);
+            hash = fnv1a(hash, &buf[..len]);
+        }
+
+        let mut header = [0; HEADER_LEN];
+        header[0..8].copy_from_slice(&MAGIC);
+        header[8..12].copy_from_slice(&VERSION.to_le_bytes());
+        header[12..16].copy_from_slice(&V::TAG.to_le_bytes());
+        header[16..24].copy_from_slice(&(self.capacity as u64).to_le_bytes());
+        header[24..32].copy_from_slice(&hash.to_le_bytes());
+        out.write_all(&header)?;
+
+        for nodes in self.data.chunks(buf.len() / V::SIZE) {
+            let len = Self::encode_chunk(nodes, &mut buf);
+            out.write_all(&buf[..len])?;
+        }
+        Ok(())
+    }
+
+    /// Encode `nodes` into the start of `buf`, returning the number of bytes
+    /// written.
+    fn encode_chunk(nodes: &[V], buf: &mut [u8]) -> usize {
+        for (v, out) in nodes.iter().zip(buf.chunks_mut(V::SIZE)) {
+            v.encode(out);
+        }
+        nodes.len() * V::SIZE
+    }
+}
+
+/// A read-only `FenwickTree` which answers queries directly from a snapshot
+/// written by `FenwickTree::write_snapshot`, decoding only the nodes each
+/// query visits. The snapshot may be borrowed from anywhere, such as a
+/// memory-mapped file, and need not be aligned.
+///
+/// # Examples
+/// ```
+/// use cuml_map::{FenwickTree, FenwickTreeView};
+///
+/// let ft: FenwickTree<u32> = FenwickTree::from_slice(&[1, 0, 4, 2]);
+/// let mut bytes = Vec::new();
+/// ft.write_snapshot(&mut bytes).unwrap();
+///
+/// let view: FenwickTreeView<u32> = FenwickTreeView::new(&bytes).unwrap();
+/// assert_eq!(view.get_cuml(2), 5);
+/// assert_eq!(view.get_single(3), 2);
+/// assert_eq!(view.get_quantile(6), Some(3));
+/// ```
+pub struct FenwickTreeView<'a, V> {
+    capacity: usize,
+    data: &'a [u8],
+    values: PhantomData<V>,
+}
+
+impl<'a, V> FenwickTreeView<'a, V>
+where
+    V: SnapshotValue,
+{
+    /// Check the header and checksum of a snapshot, and create a view of it.
+    pub fn new(bytes: &'a [u8]) -> Result<Self, SnapshotError> {
+        let view = Self::new_unverified(bytes)?;
+        if fnv1a(FNV_OFFSET, view.data) != read_u64(bytes, 24) {
+            return Err(SnapshotError::BadChecksum);
+        }
+        Ok(view)
+    }
+
+    /// Check the header of a snapshot, and create a view of it, but do not
+    /// verify its checksum. This takes constant time rather than time linear
+    /// in the size of the snapshot, but gives no protection against corrupt
+    /// values.
+    pub fn new_unverified(bytes: &'a [u8]) -> Result<Self, SnapshotError> {
+        if bytes.len() < HEADER_LEN {
+            return Err(SnapshotError::BadLength);
+        }
+        if bytes[0..8] != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        match read_u32(bytes, 8) {
+            VERSION => (),
+            v => return Err(SnapshotError::UnsupportedVersion(v)),
+        }
+        match read_u32(bytes, 12) {
+            t if t == V::TAG => (),
+            found => return Err(SnapshotError::WrongValueType { expected: V::TAG, found }),
+        }
+        let capacity = read_u64(bytes, 16);
+        let data = &bytes[HEADER_LEN..];
+        if capacity.checked_mul(V::SIZE as u64) != Some(data.len() as u64) {
+            return Err(SnapshotError::BadLength);
+        }
+        Ok(FenwickTreeView {
+            capacity: capacity as usize,
+            data,
+            values: PhantomData,
+        })
+    }
+
+    /// Get the number of keys covered by the tree.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn node(&self, i: usize) -> V {
+        V::decode(&self.data[i * V::SIZE..(i + 1) * V::SIZE])
+    }
+
+    /// Copy the snapshot into an owned `FenwickTree`.
+    pub fn to_tree(&self) -> FenwickTree<V> {
+        FenwickTree {
+            capacity: self.capacity,
+            data: (0..self.capacity).map(|i| self.node(i)).collect(),
+            keys: PhantomData,
+        }
+    }
+
+    /// Get the cumulative value at key `key`, as for `CumlMap::get_cuml`.
+    pub fn get_cuml(&self, key: usize) -> V {
+        prefix_sum(self.capacity, |i| self.node(i), key)
+    }
+
+    /// Get the value at key `key`, as for `CumlMap::get_single`.
+    pub fn get_single(&self, key: usize) -> V {
+        single_value(self.capacity, |i| self.node(i), key)
+    }
+
+    /// Get the sum of the values at keys within `range`, as for
+    /// `CumlMap::get_range`.
+    pub fn get_range<R>(&self, range: R) -> V
+    where
+        R: RangeBounds<usize>,
+    {
+        let (lo, hi) = clamp_range(range, 0, self.capacity);
+        range_sum(self.capacity, |i| self.node(i), lo, hi)
+    }
+
+    /// Get the first key at which the cumulative value equals or exceeds
+    /// `quant`, as for `CumlMap::get_quantile`.
+    pub fn get_quantile(&self, quant: V) -> Option<usize>
+    where
+        V: PartialOrd,
+    {
+        quantile(self.capacity, |i| self.node(i), quant)
+    }
+}
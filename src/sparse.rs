@@ -0,0 +1,343 @@
+use std::cmp;
+use std::collections::hash_map::{self, HashMap};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::{Bound, RangeBounds};
+use std::vec;
+
+use algebra::*;
+use cmap::*;
+
+/*****************************************************************************
+ * Binary Index Tree over the whole u64 key space
+ *****************************************************************************/
+
+/// A `FenwickTree` over every `u64` key, which stores its nodes in a
+/// `HashMap` and allocates only those nodes which an update has touched.
+/// This needs no knowledge of the keys in advance, unlike a
+/// `CompressedFenwickTree`, and no rebalancing, unlike a `CumlTree`.
+///
+/// The nodes are laid out as in a `FenwickTree` with a capacity of `2^64`,
+/// so each operation visits at most 65 nodes. An insertion allocates every
+/// node on its update path which is not already there: for keys spread
+/// evenly over the key space this is around 30 nodes per key, less the
+/// nodes shared with keys inserted earlier. A `CumlTree` allocates one node
+/// per key, so for such keys it will usually take far less memory, and less
+/// time as well; the `*_sparse` benchmarks compare the two. Nodes are never
+/// freed, even if their values return to zero.
+///
+/// # Examples
+/// ```
+/// use cuml_map::{CumlMap, SparseFenwickTree};
+///
+/// let mut ft: SparseFenwickTree<u32> = SparseFenwickTree::new();
+/// ft.insert(7, 1);
+/// ft.insert(1 << 40, 2);
+/// ft.insert(u64::MAX, 4);
+/// assert_eq!(ft.get_cuml(1 << 50), 3);
+/// assert_eq!(ft.get_range(8..), 6);
+/// assert_eq!(ft.get_quantile(2), Some(1 << 40));
+/// assert_eq!(ft.get_quantile(7), Some(u64::MAX));
+/// ```
+#[derive(Clone)]
+pub struct SparseFenwickTree<V> {
+    nodes: HashMap<u64, V>,
+}
+
+impl<V: Group> SparseFenwickTree<V> {
+    /// Create an empty `SparseFenwickTree` object.
+    pub fn new() -> Self {
+        SparseFenwickTree {
+            nodes: HashMap::new(),
+        }
+    }
+
+    /// Get the number of nodes allocated so far. Each takes the space of a
+    /// `(u64, V)` pair in the tree's `HashMap`.
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Get the node at position `i`, or `zero` if it has not been allocated.
+    fn node<'a>(&'a self, i: u64, zero: &'a V) -> &'a V {
+        self.nodes.get(&i).unwrap_or(zero)
+    }
+
+    /// Update the node at every position which covers `key`, with the
+    /// function `f`, allocating any which are not already there.
+    fn update<F>(&mut self, key: u64, f: F)
+    where
+        F: Fn(&V) -> V,
+    {
+        let mut i = key;
+        loop {
+            match self.nodes.entry(i) {
+                hash_map::Entry::Occupied(mut e) => {
+                    let v = f(e.get());
+                    e.insert(v);
+                }
+                hash_map::Entry::Vacant(e) => {
+                    e.insert(f(&V::identity()));
+                }
+            }
+            if i == 0 {
+                return;
+            }
+            match i.checked_add(1 << i.trailing_zeros()) {
+                Some(next) => i = next,
+                None => return,
+            }
+        }
+    }
+
+    /// Get the sum of the values at keys up to and including `key`.
+    fn prefix_sum(&self, key: u64) -> V {
+        let mut sum = V::identity();
+        let mut i = key;
+        loop {
+            if let Some(v) = self.nodes.get(&i) {
+                sum = sum.combine(v);
+            }
+            if i == 0 {
+                return sum;
+            }
+            i &= i - 1;
+        }
+    }
+
+    /// Get the sum of the values at keys in `[lo, hi]`, walking the two
+    /// prefix sums down only until they meet.
+    fn sum_between(&self, lo: u64, hi: u64) -> V {
+        if lo == 0 {
+            return self.prefix_sum(hi);
+        }
+        let zero = V::identity();
+        let mut add = V::identity();
+        let mut sub = V::identity();
+        let mut i = hi;
+        let mut j = lo - 1;
+        while i != j {
+            if i > j {
+                add = add.combine(self.node(i, &zero));
+                i &= i - 1;
+            } else {
+                sub = sub.combine(self.node(j, &zero));
+                j &= j - 1;
+            }
+        }
+        add.combine_inverse(&sub)
+    }
+
+    /// Iterate over every key with a non-zero value, with that value, in key
+    /// order. This takes time `O(n log n)` in the number of nodes to start.
+    ///
+    /// # Examples
+    /// ```
+    /// use cuml_map::{CumlMap, SparseFenwickTree};
+    ///
+    /// let mut ft: SparseFenwickTree<i32> = SparseFenwickTree::new();
+    /// ft.insert(1 << 60, 2);
+    /// ft.insert(3, 1);
+    /// ft.insert(5, 0);
+    /// let v: Vec<_> = ft.iter().collect();
+    /// assert_eq!(v, vec![(3, 1), (1 << 60, 2)]);
+    /// ```
+    pub fn iter(&self) -> SparseFenwickIter<'_, V> {
+        // Every key with a non-zero value was updated at some point, and so
+        // has a node of its own.
+        let mut keys: Vec<u64> = self.nodes.keys().cloned().collect();
+        keys.sort_unstable();
+        SparseFenwickIter {
+            tree: self,
+            keys: keys.into_iter(),
+        }
+    }
+
+    /// Add the value at every key of `other` to the value at the same key in
+    /// this tree. This takes time linear in the number of nodes of `other`.
+    pub fn merge_from(&mut self, other: &Self) {
+        self.merge_with(other, V::combine);
+    }
+
+    /// Subtract the value at every key of `other` from the value at the same
+    /// key in this tree, in the same way as `merge_from`.
+    pub fn subtract(&mut self, other: &Self) {
+        self.merge_with(other, V::combine_inverse);
+    }
+
+    /// Replace the value `x` at every key with `f(x, y)`, where `y` is the
+    /// value at the same key of `other`. Both trees share the same layout,
+    /// so this can be done node by node.
+    fn merge_with<F>(&mut self, other: &Self, f: F)
+    where
+        F: Fn(&V, &V) -> V,
+    {
+        for (&i, y) in &other.nodes {
+            let x = self.nodes.entry(i).or_insert_with(V::identity);
+            *x = f(x, y);
+        }
+    }
+}
+
+impl<V: Group> CumlMap for SparseFenwickTree<V> {
+    type Key = u64;
+    type Value = V;
+
+    fn insert(&mut self, key: Self::Key, val: Self::Value) {
+        if val != V::identity() {
+            self.update(key, |x| x.combine(&val));
+        }
+    }
+
+    fn set(&mut self, key: Self::Key, val: Self::Value) {
+        let old = self.get_single(key);
+        if val != old {
+            self.update(key, |x| x.combine_inverse(&old).combine(&val));
+        }
+    }
+
+    fn remove(&mut self, key: Self::Key) -> Self::Value {
+        let old = self.get_single(key);
+        if old != V::identity() {
+            self.update(key, |x| x.combine_inverse(&old));
+        }
+        old
+    }
+
+    fn get_cuml(&self, key: Self::Key) -> Self::Value {
+        self.prefix_sum(key)
+    }
+
+    fn get_single(&self, key: Self::Key) -> Self::Value {
+        let mut val = match self.nodes.get(&key) {
+            None => return V::identity(),
+            Some(v) => v.clone(),
+        };
+        if key == 0 {
+            return val;
+        }
+        let zero = V::identity();
+        let parent = key & (key - 1);
+        let mut i = key - 1;
+        while parent != i {
+            val = val.combine_inverse(self.node(i, &zero));
+            i &= i - 1;
+        }
+        val
+    }
+
+    fn get_range<R>(&self, range: R) -> Self::Value
+    where
+        R: RangeBounds<Self::Key>,
+    {
+        let lo = match range.start_bound() {
+            Bound::Included(&x) => x,
+            Bound::Excluded(&x) => match x.checked_add(1) {
+                Some(x) => x,
+                None => return V::identity(),
+            },
+            Bound::Unbounded => 0,
+        };
+        let hi = match range.end_bound() {
+            Bound::Included(&x) => x,
+            Bound::Excluded(&x) => match x.checked_sub(1) {
+                Some(x) => x,
+                None => return V::identity(),
+            },
+            Bound::Unbounded => u64::MAX,
+        };
+        if lo > hi {
+            return V::identity();
+        }
+        self.sum_between(lo, hi)
+    }
+
+    /// Find the first key at which the cumulative value reaches `quant`, by
+    /// the same binary descent as `FenwickTree::get_quantile`, over a tree
+    /// of capacity `2^64`.
+    fn get_quantile(&self, quant: Self::Value) -> Option<Self::Key>
+    where
+        V: PartialOrd,
+    {
+        // A value which is not ordered against itself, such as NaN, is never
+        // reached.
+        quant.partial_cmp(&quant)?;
+        let zero = V::identity();
+        let first = self.node(0, &zero);
+        if first.partial_cmp(&quant) != Some(cmp::Ordering::Less) {
+            return Some(0);
+        }
+        let mut step = 1 << 63;
+        let mut ix: u64 = 0;
+        let mut quant = quant.combine_inverse(first);
+        while step > 0 {
+            // `ix` is a multiple of `2 * step`, so this cannot overflow.
+            let n = self.node(ix + step, &zero);
+            if *n < quant {
+                ix += step;
+                quant = quant.combine_inverse(n);
+            }
+            step >>= 1;
+        }
+        if quant == zero {
+            Some(ix)
+        } else {
+            ix.checked_add(1)
+        }
+    }
+}
+
+impl<V: Group> Default for SparseFenwickTree<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V: Group + fmt::Debug> fmt::Debug for SparseFenwickTree<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<V: Group> PartialEq for SparseFenwickTree<V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl<V: Group + Eq> Eq for SparseFenwickTree<V> {}
+
+impl<V: Group + Hash> Hash for SparseFenwickTree<V> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_entries(self.iter(), state)
+    }
+}
+
+/// An iterator over the keys of a `SparseFenwickTree` with non-zero values.
+pub struct SparseFenwickIter<'a, V: 'a> {
+    tree: &'a SparseFenwickTree<V>,
+    keys: vec::IntoIter<u64>,
+}
+
+impl<'a, V: Group> Iterator for SparseFenwickIter<'a, V> {
+    type Item = (u64, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let tree = self.tree;
+        self.keys
+            .by_ref()
+            .map(|k| (k, tree.get_single(k)))
+            .find(|e| e.1 != V::identity())
+    }
+}
+
+impl<'a, V: Group> DoubleEndedIterator for SparseFenwickIter<'a, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let tree = self.tree;
+        self.keys
+            .by_ref()
+            .rev()
+            .map(|k| (k, tree.get_single(k)))
+            .find(|e| e.1 != V::identity())
+    }
+}
@@ -1,6 +1,8 @@
-extern crate test;
-use self::test::Bencher;
 use super::*;
+use std::ops::Bound;
+use std::num::{Saturating, Wrapping};
+#[cfg(feature = "serde")]
+extern crate serde_json;
 
 macro_rules! test_trivial {
     ($testn:ident, $type:expr) => {
@@ -37,6 +39,8 @@ macro_rules! test_trivial {
 test_trivial!(ftf_trivial, FenwickTree::with_capacity(5));
 test_trivial!(fte_trivial, ExtensibleFenwickTree::new());
 test_trivial!(rbt_trivial, CumlTree::new());
+test_trivial!(cft_trivial, CompressedFenwickTree::new(vec![4, 2, 0, 1, 1000]));
+test_trivial!(sft_trivial, SparseFenwickTree::new());
 
 macro_rules! test_small_neg_mono {
     ($testn:ident, $type:expr) => {
@@ -69,6 +73,8 @@ macro_rules! test_small_neg_mono {
 test_small_neg_mono!(ftf_small_neg_mono, FenwickTree::with_capacity(5));
 test_small_neg_mono!(fte_small_neg_mono, ExtensibleFenwickTree::new());
 test_small_neg_mono!(rbt_small_neg_mono, CumlTree::new());
+test_small_neg_mono!(cft_small_neg_mono, CompressedFenwickTree::new(0..4));
+test_small_neg_mono!(sft_small_neg_mono, SparseFenwickTree::new());
 
 fn load_updates(fname: &str) -> (usize, Vec<i64>, Vec<i64>) {
     use std::fs::File;
@@ -97,18 +103,11 @@ fn load_updates(fname: &str) -> (usize, Vec<i64>, Vec<i64>) {
     (cap, keys, vals)
 }
 
-macro_rules! bench_build {
+macro_rules! test_build {
     ($testn:ident, $k:ty, $fname:expr, $init:expr) => {
-        #[bench]
-        fn $testn(b: &mut Bencher) {
+        #[test]
+        fn $testn() {
             let (_cap, keys, vals) = load_updates($fname);
-            b.iter(|| {
-                let mut cm = $init;
-                for i in 0..keys.len() {
-                    cm.insert(keys[i] as $k, vals[i]);
-                }
-            });
-
             let mut cm = $init;
             let mut c50 = 0;
             let mut c1 = 0;
@@ -127,51 +126,17 @@ macro_rules! bench_build {
     };
 }
 
-bench_build!(ftf_build_1, usize, "src/bench_1", FenwickTree::with_capacity(1000));
-bench_build!(fte_build_1, i64,   "src/bench_1", ExtensibleFenwickTree::new());
-bench_build!(ftc_build_1, i64,   "src/bench_1", ExtensibleFenwickTree::with_capacity(1000));
-bench_build!(rbt_build_1, i64,   "src/bench_1", CumlTree::new());
-
-macro_rules! bench_getc {
-    ($testn:ident, $k:ty, $fname:expr, $init:expr) => {
-        #[bench]
-        fn $testn(b: &mut Bencher) {
-            let (_cap, keys, vals) = load_updates($fname);
-            let mut cm = $init;
-            for i in 0..keys.len() {
-                cm.insert(keys[i] as $k, vals[i]);
-            }
-
-            let mx = *keys.iter().max().unwrap();
-            let mut c = 0;
-            b.iter(|| {
-                for i in 0..mx {
-                    let j = test::black_box(i);
-                    c = cm.get_cuml(j as $k);
-                }
-            });
-        }
-    };
-}
-
-bench_getc!(ftf_getc_1, usize, "src/bench_1", FenwickTree::with_capacity(1000));
-bench_getc!(fte_getc_1, i64,   "src/bench_1", ExtensibleFenwickTree::new());
-bench_getc!(ftc_getc_1, i64,   "src/bench_1", ExtensibleFenwickTree::with_capacity(1000));
-bench_getc!(rbt_getc_1, i64,   "src/bench_1", CumlTree::new());
+test_build!(ftf_build_1, usize, "src/bench_1", FenwickTree::with_capacity(1000));
+test_build!(fte_build_1, i64,   "src/bench_1", ExtensibleFenwickTree::new());
+test_build!(ftc_build_1, i64,   "src/bench_1", ExtensibleFenwickTree::with_capacity(1000));
+test_build!(rbt_build_1, i64,   "src/bench_1", CumlTree::new());
+test_build!(sft_build_1, u64,   "src/bench_1", SparseFenwickTree::new());
 
-macro_rules! bench_degen {
+macro_rules! test_degen {
     ($testn:ident, $k:ty, $init:expr) => {
-        #[bench]
-        fn $testn(b: &mut Bencher)
-        {
+        #[test]
+        fn $testn() {
             let n = 1000;
-            b.iter(|| {
-                let mut cm = $init;
-                for i in 1..n {
-                    cm.insert(i as $k, i);
-                }
-            });
-
             let mut cm = $init;
             let mut tot = 0;
             for i in 1..n {
@@ -184,36 +149,11 @@ macro_rules! bench_degen {
     };
 }
 
-bench_degen!(ftf_build_degen, usize, FenwickTree::with_capacity(1000));
-bench_degen!(fte_build_degen, i64,   ExtensibleFenwickTree::new());
-bench_degen!(ftc_build_degen, i64,   ExtensibleFenwickTree::with_capacity(1000));
-bench_degen!(rbt_build_degen, i64,   CumlTree::new());
-
-macro_rules! bench_getc_degen {
-    ($testn:ident, $k:ty, $init:expr) => {
-        #[bench]
-        fn $testn(b: &mut Bencher) {
-            let n = 1000;
-            let mut cm = $init;
-            for i in 1..n {
-                cm.insert(i as $k, i);
-            }
-
-            let mut c = 0;
-            b.iter(|| {
-                for i in 0..n {
-                    let j = test::black_box(i);
-                    c = cm.get_cuml(j as $k);
-                }
-            });
-        }
-    };
-}
-
-bench_getc_degen!(ftf_getc_degen, usize, FenwickTree::with_capacity(1000));
-bench_getc_degen!(fte_getc_degen, i64,   ExtensibleFenwickTree::new());
-bench_getc_degen!(ftc_getc_degen, i64,   ExtensibleFenwickTree::with_capacity(1000));
-bench_getc_degen!(rbt_getc_degen, i64,   CumlTree::new());
+test_degen!(ftf_build_degen, usize, FenwickTree::with_capacity(1000));
+test_degen!(fte_build_degen, i64,   ExtensibleFenwickTree::new());
+test_degen!(ftc_build_degen, i64,   ExtensibleFenwickTree::with_capacity(1000));
+test_degen!(rbt_build_degen, i64,   CumlTree::new());
+test_degen!(sft_build_degen, u64,   SparseFenwickTree::new());
 
 macro_rules! test_neg_key {
     ($testn:ident, $init:expr) => {
@@ -251,6 +191,7 @@ macro_rules! test_neg_key {
 
 test_neg_key!(fte_neg_key, ExtensibleFenwickTree::new());
 test_neg_key!(rbt_neg_key, CumlTree::new());
+test_neg_key!(cft_neg_key, CompressedFenwickTree::new(vec![-2, -1, 1, 3]));
 
 // ExtensibleFenwickTree specific tests
 
@@ -285,3 +226,2082 @@ macro_rules! test_oob_query {
 
 test_oob_query!(fwt_oob_query, FenwickTree::with_capacity(10));
 test_oob_query!(eft_oob_query, ExtensibleFenwickTree::with_capacity(10));
+test_oob_query!(cft_oob_query, CompressedFenwickTree::new(vec![3, 9]));
+test_oob_query!(sft_oob_query, SparseFenwickTree::new());
+
+macro_rules! test_set_remove {
+    ($testn:ident, $init:expr) => {
+        #[test]
+        fn $testn() {
+            let mut t = $init;
+            t.insert(0, 1);
+            t.insert(1, 2);
+            t.insert(2, 3);
+            t.insert(4, 5);
+
+            t.set(1, 7);
+            assert_eq!(t.get_single(1), 7);
+            assert_eq!(t.get_cuml(4), 16);
+
+            t.set(2, 1);
+            assert_eq!(t.get_single(2), 1);
+            assert_eq!(t.get_cuml(2), 9);
+
+            t.set(3, 4);
+            assert_eq!(t.get_single(3), 4);
+            assert_eq!(t.get_cuml(4), 18);
+
+            assert_eq!(t.remove(1), 7);
+            assert_eq!(t.get_single(1), 0);
+            assert_eq!(t.get_cuml(1), 1);
+            assert_eq!(t.get_cuml(4), 11);
+            assert_eq!(t.remove(1), 0);
+
+            assert_eq!(t.remove(0), 1);
+            assert_eq!(t.get_quantile(1), Some(2));
+            assert_eq!(t.get_quantile(6), Some(4));
+            assert_eq!(t.get_quantile(11), None);
+        }
+    };
+}
+
+test_set_remove!(ftf_set_remove, FenwickTree::<u32>::with_capacity(5));
+test_set_remove!(fte_set_remove, ExtensibleFenwickTree::<u32>::new());
+test_set_remove!(rbt_set_remove, CumlTree::<i64, u32>::new());
+test_set_remove!(cft_set_remove, CompressedFenwickTree::<i64, u32>::new(0..5));
+test_set_remove!(sft_set_remove, SparseFenwickTree::<u32>::new());
+
+// CumlTree specific tests
+
+#[test]
+fn rbt_remove_many() {
+    use std::collections::BTreeMap;
+
+    let mut t = CumlTree::new();
+    let mut m = BTreeMap::new();
+    let mut x: u64 = 12345;
+    for i in 0..2000 {
+        x = x.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let k = ((x >> 33) % 200) as i64;
+        if i % 3 == 0 {
+            assert_eq!(t.remove(k), m.remove(&k).unwrap_or(0));
+        } else {
+            t.insert(k, i);
+            *m.entry(k).or_insert(0) += i;
+        }
+        t.check_sums(&m);
+        assert_eq!(t.len(), m.len());
+    }
+
+    let mut tot = 0;
+    for k in 0..200 {
+        let v = m.get(&k).cloned().unwrap_or(0);
+        tot += v;
+        assert_eq!(t.get_single(k), v);
+        assert_eq!(t.get_cuml(k), tot);
+    }
+
+    for k in 0..200 {
+        t.remove(k);
+        m.remove(&k);
+        t.check_sums(&m);
+    }
+    assert!(t.is_empty());
+    assert_eq!(t.get_cuml(200), 0);
+    assert_eq!(t.get_quantile(1), None);
+}
+
+#[test]
+fn rbt_insert_balanced() {
+    let mut t = CumlTree::new();
+    for i in 0..1000 {
+        t.insert(i, 1);
+        t.check_invariants();
+    }
+    for i in (1000..2000).rev() {
+        t.insert(i, 1);
+    }
+    t.check_invariants();
+    assert_eq!(t.len(), 2000);
+}
+
+#[test]
+fn rbt_prune() {
+    let mut t = CumlTree::with_pruning();
+    t.insert(1, 5);
+    t.insert(2, 3);
+    t.insert(3, -4);
+    assert_eq!(t.len(), 3);
+
+    t.insert(2, -3);
+    assert_eq!(t.len(), 2);
+    assert_eq!(t.get_single(2), 0);
+    assert_eq!(t.get_cuml(3), 1);
+
+    t.set(3, 0);
+    assert_eq!(t.len(), 1);
+    t.insert(4, 0);
+    assert_eq!(t.len(), 1);
+    t.set(1, 2);
+    assert_eq!(t.len(), 1);
+    assert_eq!(t.get_cuml(4), 2);
+    t.check_invariants();
+
+    let mut u = CumlTree::new();
+    u.insert(1, 5);
+    u.insert(1, -5);
+    assert_eq!(u.len(), 1);
+    assert_eq!(u.get_single(1), 0);
+}
+
+#[test]
+fn rbt_prune_churn() {
+    let mut t = CumlTree::with_pruning();
+    let mut x: u64 = 987654321;
+    for i in 0..5000i64 {
+        x = x.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        t.insert(i, ((x >> 40) % 10 + 1) as i64);
+        if i >= 50 {
+            let old = t.get_single(i - 50);
+            t.insert(i - 50, -old);
+        }
+        assert!(t.len() <= 50);
+    }
+    t.check_invariants();
+    assert_eq!(t.len(), 50);
+    assert_eq!(t.get_cuml(4949), 0);
+}
+
+#[test]
+fn rbt_remove_reinsert() {
+    let mut t = CumlTree::new();
+    for round in 0..3 {
+        for i in 0..200i64 {
+            t.insert((i * 37) % 200, i + round);
+        }
+        t.check_invariants();
+        for i in (0..200i64).rev() {
+            t.remove((i * 53) % 200);
+            if i % 20 == 0 {
+                t.check_invariants();
+            }
+        }
+        assert!(t.is_empty());
+        assert_eq!(t.get_cuml(199), 0);
+    }
+}
+
+#[test]
+fn rbt_drop_large() {
+    let n = 1 << 20;
+    let t = CumlTree::from_sorted((0..n).map(|i| (i, 1u32)));
+    assert_eq!(t.get_cuml(n), n as u32);
+    drop(t);
+}
+
+macro_rules! test_range {
+    ($testn:ident, $init:expr) => {
+        #[test]
+        fn $testn() {
+            let mut t = $init;
+            t.insert(0, 1);
+            t.insert(1, 2);
+            t.insert(2, 3);
+            t.insert(4, 5);
+            t.insert(7, 4);
+
+            assert_eq!(t.get_range(..), 15);
+            assert_eq!(t.get_range(0..), 15);
+            assert_eq!(t.get_range(..=4), 11);
+            assert_eq!(t.get_range(..4), 6);
+            assert_eq!(t.get_range(1..=4), 10);
+            assert_eq!(t.get_range(1..4), 5);
+            assert_eq!(t.get_range(2..=2), 3);
+            assert_eq!(t.get_range(2..2), 0);
+            assert_eq!(t.get_range(3..=3), 0);
+            assert_eq!(t.get_range((Bound::Included(4), Bound::Excluded(1))), 0);
+            assert_eq!(t.get_range(3..), 9);
+            assert_eq!(t.get_range(5..=100), 4);
+            assert_eq!(t.get_range(8..), 0);
+            assert_eq!(t.get_range((Bound::Excluded(0), Bound::Included(2))), 5);
+            assert_eq!(t.get_range((Bound::Excluded(4), Bound::Unbounded)), 4);
+        }
+    };
+}
+
+test_range!(ftf_range, FenwickTree::<u32>::with_capacity(8));
+test_range!(fte_range, ExtensibleFenwickTree::<u32>::new());
+test_range!(rbt_range, CumlTree::<i64, u32>::new());
+test_range!(cft_range, CompressedFenwickTree::<i64, u32>::new(vec![0, 1, 2, 4, 7, 50]));
+test_range!(sft_range, SparseFenwickTree::<u32>::new());
+
+#[test]
+fn ftf_range_exhaustive() {
+    let n = 37;
+    let mut t = FenwickTree::with_capacity(n);
+    for i in 0..n {
+        t.insert(i, (i * 7 % 5) as u64);
+    }
+    for lo in 0..n + 2 {
+        for hi in 0..n + 2 {
+            let expected: u64 = (lo..hi).filter(|&i| i < n).map(|i| (i * 7 % 5) as u64).sum();
+            assert_eq!(t.get_range(lo..hi), expected);
+        }
+    }
+}
+
+#[test]
+fn fte_range_neg_key() {
+    let mut t = ExtensibleFenwickTree::new();
+    t.insert(-5, 1);
+    t.insert(-1, 2);
+    t.insert(3, 4);
+
+    assert_eq!(t.get_range(-5..=-1), 3);
+    assert_eq!(t.get_range(-4..), 6);
+    assert_eq!(t.get_range(..0), 3);
+    assert_eq!(t.get_range(-100..-5), 0);
+    assert_eq!(t.get_range(i64::MIN..=i64::MAX), 7);
+}
+
+#[test]
+fn rbt_range_exhaustive() {
+    let n = 37;
+    let mut t = CumlTree::new();
+    for i in (0..n).rev() {
+        t.insert(i * 2, (i * 7 % 5) as u64);
+    }
+    for lo in -1..2 * n + 1 {
+        for hi in -1..2 * n + 1 {
+            let expected: u64 = (0..n)
+                .filter(|&i| lo <= i * 2 && i * 2 < hi)
+                .map(|i| (i * 7 % 5) as u64)
+                .sum();
+            assert_eq!(t.get_range(lo..hi), expected);
+        }
+    }
+}
+
+#[test]
+fn ftf_iter() {
+    let mut t = FenwickTree::with_capacity(6);
+    t.insert(0, 1);
+    t.insert(2, 3);
+    t.insert(3, 2);
+    t.insert(5, 4);
+
+    let v: Vec<_> = t.iter().collect();
+    assert_eq!(v, vec![(0, 1), (1, 0), (2, 3), (3, 2), (4, 0), (5, 4)]);
+    let v: Vec<_> = t.iter().skip_zeros().rev().collect();
+    assert_eq!(v, vec![(5, 4), (3, 2), (2, 3), (0, 1)]);
+    let v: Vec<_> = t.cuml_iter().collect();
+    assert_eq!(v, vec![(0, 1), (1, 1), (2, 4), (3, 6), (4, 6), (5, 10)]);
+    let v: Vec<_> = t.range(1..4).cumulative().collect();
+    assert_eq!(v, vec![(1, 1), (2, 4), (3, 6)]);
+    let v: Vec<_> = t.range(1..=4).skip_zeros().cumulative().rev().collect();
+    assert_eq!(v, vec![(3, 6), (2, 4)]);
+    assert_eq!(t.range((Bound::Included(4), Bound::Excluded(2))).next(), None);
+    assert_eq!(t.range(10..).next(), None);
+
+    let mut it = t.iter().skip_zeros().cumulative();
+    assert_eq!(it.next(), Some((0, 1)));
+    assert_eq!(it.next_back(), Some((5, 10)));
+    assert_eq!(it.next_back(), Some((3, 6)));
+    assert_eq!(it.next(), Some((2, 4)));
+    assert_eq!(it.next(), None);
+    assert_eq!(it.next_back(), None);
+}
+
+#[test]
+fn fte_iter() {
+    let mut t = ExtensibleFenwickTree::new();
+    t.insert(-3, 1);
+    t.insert(0, 3);
+    t.insert(2, 2);
+
+    let v: Vec<_> = t.iter().skip_zeros().collect();
+    assert_eq!(v, vec![(-3, 1), (0, 3), (2, 2)]);
+    let v: Vec<_> = t.cuml_iter().skip_while(|&(k, _)| k < -3).take(4).collect();
+    assert_eq!(v, vec![(-3, 1), (-2, 1), (-1, 1), (0, 4)]);
+    let v: Vec<_> = t.range(-2..=2).skip_zeros().cumulative().rev().collect();
+    assert_eq!(v, vec![(2, 6), (0, 4)]);
+    assert_eq!(t.range(..-100).next(), None);
+    assert_eq!(ExtensibleFenwickTree::<u32>::new().iter().next(), None);
+}
+
+#[test]
+fn rbt_iter() {
+    use std::collections::BTreeMap;
+
+    let mut t = CumlTree::new();
+    let mut m = BTreeMap::new();
+    let mut x: u64 = 4242;
+    for i in 0..500 {
+        x = x.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let k = ((x >> 33) % 100) as i64;
+        if i % 4 == 0 {
+            t.remove(k);
+            m.remove(&k);
+        } else {
+            t.insert(k, i);
+            *m.entry(k).or_insert(0) += i;
+        }
+    }
+
+    let v: Vec<_> = t.iter().collect();
+    let w: Vec<_> = m.iter().map(|(k, v)| (k, *v)).collect();
+    assert_eq!(v, w);
+    let v: Vec<_> = t.iter().rev().collect();
+    let w: Vec<_> = m.iter().rev().map(|(k, v)| (k, *v)).collect();
+    assert_eq!(v, w);
+
+    let v: Vec<_> = t.range(20..=70).collect();
+    let w: Vec<_> = m.range(20..=70).map(|(k, v)| (k, *v)).collect();
+    assert_eq!(v, w);
+
+    let v: Vec<_> = t.range(20..70).cumulative().collect();
+    let w: Vec<_> = m.range(20..70).map(|(k, _)| (k, t.get_cuml(*k))).collect();
+    assert_eq!(v, w);
+    let v: Vec<_> = t.range(20..70).cumulative().rev().collect();
+    let w: Vec<_> = m.range(20..70).rev().map(|(k, _)| (k, t.get_cuml(*k))).collect();
+    assert_eq!(v, w);
+    let v: Vec<_> = t.cuml_iter().collect();
+    let w: Vec<_> = m.keys().map(|k| (k, t.get_cuml(*k))).collect();
+    assert_eq!(v, w);
+
+    let mut it = t.iter();
+    let mut n = 0;
+    while it.next().is_some() {
+        n += 1;
+        if it.next_back().is_some() {
+            n += 1;
+        }
+    }
+    assert_eq!(n, t.len());
+
+    assert_eq!(t.range(200..).next(), None);
+    assert_eq!(t.range(50..50).next(), None);
+    assert_eq!(CumlTree::<i64, i64>::new().iter().next_back(), None);
+}
+
+#[test]
+fn ftf_from_slice() {
+    let vals: Vec<i64> = (0..100).map(|i| (i * 37 % 11) - 5).collect();
+    let t = FenwickTree::from_slice(&vals);
+    let mut u = FenwickTree::with_capacity(100);
+    for (i, &v) in vals.iter().enumerate() {
+        u.insert(i, v);
+    }
+
+    let mut tot = 0;
+    for (i, &v) in vals.iter().enumerate() {
+        tot += v;
+        assert_eq!(t.get_single(i), v);
+        assert_eq!(t.get_cuml(i), tot);
+        assert_eq!(t.get_cuml(i), u.get_cuml(i));
+    }
+
+    let c: FenwickTree<i64> = vals.iter().cloned().collect();
+    assert_eq!(c.get_cuml(99), tot);
+    let e: FenwickTree<i64> = FenwickTree::from_slice(&[]);
+    assert_eq!(e.iter().next(), None);
+}
+
+#[test]
+fn fte_extend_both_ways() {
+    let mut t = ExtensibleFenwickTree::with_extent(0, 3);
+    let mut keys = vec![1, 2, -7, 40, -100, 0, 300];
+    for (i, &k) in keys.iter().enumerate() {
+        t.insert(k, i as i64 + 1);
+    }
+    keys.sort();
+    let mut tot = 0;
+    for &k in &keys {
+        let v = t.get_single(k);
+        tot += v;
+        assert!(v > 0);
+        assert_eq!(t.get_cuml(k), tot);
+    }
+    assert_eq!(tot, 28);
+    assert_eq!(t.iter().skip_zeros().count(), keys.len());
+}
+
+#[test]
+fn rbt_from_sorted() {
+    for n in 0..130 {
+        let t = CumlTree::from_sorted((0..n).map(|i| (i * 3, i)));
+        t.check_invariants();
+        assert_eq!(t.len(), n as usize);
+        let mut tot = 0;
+        for i in 0..n {
+            tot += i;
+            assert_eq!(t.get_single(i * 3), i);
+            assert_eq!(t.get_cuml(i * 3 + 1), tot);
+        }
+    }
+
+    let mut t = CumlTree::from_sorted(vec![(1, 2), (1, 3), (4, 1), (4, 1), (9, 7)]);
+    assert_eq!(t.len(), 3);
+    assert_eq!(t.get_single(1), 5);
+    assert_eq!(t.get_single(4), 2);
+    assert_eq!(t.get_quantile(8), Some(9));
+    t.insert(2, 1);
+    t.remove(4);
+    t.check_invariants();
+    assert_eq!(t.get_cuml(9), 13);
+}
+
+#[test]
+#[should_panic]
+fn rbt_from_sorted_unsorted() {
+    CumlTree::from_sorted(vec![(1, 2), (0, 3)]);
+}
+
+#[test]
+fn rbt_from_iter() {
+    let t: CumlTree<i64, i64> = vec![(5, 1), (-2, 3), (5, 2), (0, 4)].into_iter().collect();
+    t.check_invariants();
+    let v: Vec<_> = t.iter().collect();
+    assert_eq!(v, vec![(&-2, 3), (&0, 4), (&5, 3)]);
+}
+
+macro_rules! test_group_values {
+    ($testn:ident, $init:expr) => {
+        #[test]
+        fn $testn() {
+            use std::num::Wrapping;
+
+            let mut t = $init;
+            t.insert(0, (Wrapping(250u8), vec![1i32, 2]));
+            t.insert(1, (Wrapping(10u8), vec![0, 0, 3]));
+            t.insert(3, (Wrapping(1u8), vec![-1]));
+
+            assert_eq!(t.get_cuml(1), (Wrapping(4u8), vec![1, 2, 3]));
+            assert_eq!(t.get_cuml(3), (Wrapping(5u8), vec![0, 2, 3]));
+            assert_eq!(t.get_single(1), (Wrapping(10u8), vec![0, 0, 3]));
+            assert_eq!(t.get_range(1..), (Wrapping(11u8), vec![-1, 0, 3]));
+
+            t.set(1, (Wrapping(0u8), vec![]));
+            assert_eq!(t.get_cuml(3), (Wrapping(251u8), vec![0, 2]));
+            assert_eq!(t.remove(0), (Wrapping(250u8), vec![1, 2]));
+            assert_eq!(t.get_cuml(3), (Wrapping(1u8), vec![-1]));
+        }
+    };
+}
+
+test_group_values!(ftf_group_values, FenwickTree::with_capacity(4));
+test_group_values!(fte_group_values, ExtensibleFenwickTree::new());
+test_group_values!(rbt_group_values, CumlTree::<i64, _>::new());
+
+macro_rules! test_float {
+    ($testn:ident, $init:expr) => {
+        #[test]
+        fn $testn() {
+            let mut t = $init;
+            t.insert(0, 0.5);
+            t.insert(1, 1.25);
+            t.insert(2, 0.0);
+            t.insert(4, 2.0);
+
+            assert_eq!(t.get_single(1), 1.25);
+            assert_eq!(t.get_cuml(2), 1.75);
+            assert_eq!(t.get_cuml(4), 3.75);
+            assert_eq!(t.get_range(1..), 3.25);
+
+            assert_eq!(t.get_quantile(0.25), Some(0));
+            assert_eq!(t.get_quantile(0.5), Some(0));
+            assert_eq!(t.get_quantile(0.75), Some(1));
+            assert_eq!(t.get_quantile(1.75), Some(1));
+            assert_eq!(t.get_quantile(1.8), Some(4));
+            assert_eq!(t.get_quantile(3.75), Some(4));
+            assert_eq!(t.get_quantile(3.8), None);
+            assert_eq!(t.get_quantile(f64::NAN), None);
+            assert_eq!(t.get_quantile(-1.0), Some(0));
+
+            // a NaN value counts as reaching every quantile
+            t.insert(2, f64::NAN);
+            assert!(t.get_cuml(4).is_nan());
+            assert_eq!(t.get_quantile(0.75), Some(1));
+            assert_eq!(t.get_quantile(1.8), Some(2));
+            assert_eq!(t.get_quantile(100.0), Some(2));
+        }
+    };
+}
+
+test_float!(ftf_float, FenwickTree::with_capacity(5));
+test_float!(fte_float, ExtensibleFenwickTree::new());
+test_float!(rbt_float, CumlTree::<i64, f64>::new());
+
+macro_rules! test_neg_quantile {
+    ($testn:ident, $init:expr) => {
+        #[test]
+        fn $testn() {
+            let vals = [2.0, -3.0, 1.5, 4.0, -2.5, 1.0, 3.0, -1.0];
+            let mut t = $init;
+            for (k, &v) in vals.iter().enumerate() {
+                t.insert(k as _, v);
+            }
+
+            // Whatever key is returned, the cumulative value must cross the
+            // requested quantile there.
+            for &q in &[-1.0, 0.0, 0.5, 1.0, 2.0, 3.0, 4.5, 5.0] {
+                let k = t.get_quantile(q).unwrap();
+                assert!(t.get_cuml(k) >= q);
+                if k > 0 {
+                    assert!(t.get_cuml(k - 1) < q);
+                }
+            }
+            assert_eq!(t.get_quantile(6.0), Some(6));
+            assert_eq!(t.get_quantile(6.5), None);
+        }
+    };
+}
+
+test_neg_quantile!(ftf_neg_quantile, FenwickTree::with_capacity(8));
+test_neg_quantile!(fte_neg_quantile, ExtensibleFenwickTree::new());
+test_neg_quantile!(rbt_neg_quantile, CumlTree::<i64, f64>::new());
+
+#[test]
+fn ftf_compensated() {
+    let n = 1000;
+    let vals: Vec<f64> = (0..n)
+        .map(|i| match i % 4 {
+            0 => 1e16,
+            2 => -1e16,
+            _ => 1.0,
+        })
+        .collect();
+    let plain = FenwickTree::from_slice(&vals);
+    let comp: FenwickTree<Compensated<f64>> = vals.iter().map(|&v| Compensated::new(v)).collect();
+
+    let mut plain_wrong = 0;
+    for i in (2..n).step_by(4) {
+        let exact = (i / 2) as f64;
+        assert_eq!(comp.get_cuml(i).value(), exact);
+        if plain.get_cuml(i) != exact {
+            plain_wrong += 1;
+        }
+    }
+    assert!(plain_wrong > 0);
+    assert_eq!(comp.get_single(1).value(), 1.0);
+}
+
+#[test]
+fn ftf_empty_quantile() {
+    let t: FenwickTree<u32> = FenwickTree::with_capacity(0);
+    assert_eq!(t.get_quantile(1), None);
+    let t: ExtensibleFenwickTree<u32> = ExtensibleFenwickTree::new();
+    assert_eq!(t.get_quantile(0), None);
+}
+
+macro_rules! test_quantile_zero_tie {
+    ($testn:ident, $init:expr) => {
+        #[test]
+        fn $testn() {
+            let mut t = $init;
+            for k in 0..8 {
+                t.insert(k, if k % 3 == 0 { 1 } else { 0 });
+            }
+            assert_eq!(t.get_quantile(1), Some(0));
+            assert_eq!(t.get_quantile(2), Some(3));
+            assert_eq!(t.get_quantile(3), Some(6));
+            assert_eq!(t.get_quantile(4), None);
+        }
+    };
+}
+
+test_quantile_zero_tie!(ftf_quantile_zero_tie, FenwickTree::<u32>::with_capacity(8));
+test_quantile_zero_tie!(fte_quantile_zero_tie, ExtensibleFenwickTree::<u32>::new());
+test_quantile_zero_tie!(rbt_quantile_zero_tie, CumlTree::<i64, u32>::new());
+test_quantile_zero_tie!(cft_quantile_zero_tie, CompressedFenwickTree::<i64, u32>::new(0..8));
+test_quantile_zero_tie!(sft_quantile_zero_tie, SparseFenwickTree::<u32>::new());
+
+#[test]
+fn rbt_string_keys() {
+    let mut t = CumlTree::new();
+    for (i, w) in "the quick brown fox jumps over the lazy dog".split(' ').enumerate() {
+        t.insert(w.to_string(), i as u32 + 1);
+    }
+    t.check_invariants();
+
+    assert_eq!(t.len(), 8);
+    assert_eq!(t.get_single("the".to_string()), 8);
+    assert_eq!(t.get_cuml("dog".to_string()), 12);
+    assert_eq!(t.get_range("f".to_string().."p".to_string()), 23);
+    assert_eq!(t.get_quantile(12), Some("dog".to_string()));
+    assert_eq!(t.get_quantile_ref(13).map(|s| s.as_str()), Some("fox"));
+    assert_eq!(t.remove("the".to_string()), 8);
+    let keys: Vec<&str> = t.iter().map(|(k, _)| k.as_str()).collect();
+    assert_eq!(keys, vec!["brown", "dog", "fox", "jumps", "lazy", "over", "quick"]);
+}
+
+#[test]
+fn rbt_composite_keys() {
+    let mut t = CumlTree::new();
+    t.insert(("b".to_string(), vec![1u8, 2]), 1);
+    t.insert(("a".to_string(), vec![9u8]), 2);
+    t.insert(("b".to_string(), vec![1u8]), 4);
+    t.insert(("a".to_string(), vec![]), 8);
+
+    let v: Vec<_> = t.cuml_iter().map(|(k, c)| (k.clone(), c)).collect();
+    assert_eq!(
+        v,
+        vec![
+            (("a".to_string(), vec![]), 8),
+            (("a".to_string(), vec![9]), 10),
+            (("b".to_string(), vec![1]), 14),
+            (("b".to_string(), vec![1, 2]), 15),
+        ]
+    );
+    let lo = ("a".to_string(), vec![5u8]);
+    let hi = ("b".to_string(), vec![1u8]);
+    assert_eq!(t.get_range(lo..=hi), 6);
+}
+
+#[test]
+fn rbt_unclonable_keys() {
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
+    struct Id(u64);
+
+    let t = CumlTree::from_sorted((0..10).map(|i| (Id(i * 10), 1)));
+    t.check_invariants();
+    assert_eq!(t.get_quantile_ref(4), Some(&Id(30)));
+    let v: Vec<_> = t.range(Id(25)..Id(55)).map(|(k, _)| k).collect();
+    assert_eq!(v, vec![&Id(30), &Id(40), &Id(50)]);
+}
+
+macro_rules! test_serde_roundtrip {
+    ($testn:ident, $t:ty, $init:expr) => {
+        #[test]
+        #[cfg(feature = "serde")]
+        fn $testn() {
+            let mut t: $t = $init;
+            t.insert(0, 1);
+            t.insert(1, 2);
+            t.insert(4, 5);
+            t.insert(7, 4);
+            t.insert(1, -2);
+
+            let json = serde_json::to_string(&t).unwrap();
+            let u: $t = serde_json::from_str(&json).unwrap();
+            for k in 0..10 {
+                assert_eq!(u.get_single(k), t.get_single(k));
+                assert_eq!(u.get_cuml(k), t.get_cuml(k));
+            }
+            assert_eq!(serde_json::to_string(&u).unwrap(), json);
+        }
+    };
+}
+
+test_serde_roundtrip!(ftf_serde_roundtrip, FenwickTree<i32>, FenwickTree::with_capacity(10));
+test_serde_roundtrip!(fte_serde_roundtrip, ExtensibleFenwickTree<i32>, ExtensibleFenwickTree::new());
+test_serde_roundtrip!(ftf_serde_roundtrip_u16, FenwickTree<i32, u16>, FenwickTree::with_capacity(10));
+test_serde_roundtrip!(fte_serde_roundtrip_i8, ExtensibleFenwickTree<i32, i8>, ExtensibleFenwickTree::new());
+test_serde_roundtrip!(rbt_serde_roundtrip, CumlTree<i32, i32>, CumlTree::new());
+
+#[test]
+#[cfg(feature = "serde")]
+fn ftf_serde_format() {
+    let t: FenwickTree<i32> = FenwickTree::from_slice(&[0, 3, 0, 0, 2]);
+    let json = serde_json::to_string(&t).unwrap();
+    assert_eq!(json, r#"{"capacity":5,"entries":[[1,3],[4,2]]}"#);
+
+    let bad = [
+        r#"{"capacity":5,"entries":[[1,3],[5,2]]}"#,
+        r#"{"capacity":5,"entries":[[4,3],[1,2]]}"#,
+        r#"{"capacity":5,"entries":[[1,3],[1,2]]}"#,
+        r#"{"capacity":1152921504606846975,"entries":[]}"#,
+    ];
+    for json in bad.iter() {
+        assert!(serde_json::from_str::<FenwickTree<i32>>(json).is_err());
+    }
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn fte_serde_format() {
+    let mut t = ExtensibleFenwickTree::with_extent(-4, 8);
+    t.insert(-3, 2);
+    t.insert(3, 1);
+    let json = serde_json::to_string(&t).unwrap();
+    assert_eq!(json, r#"{"offset":-4,"capacity":8,"entries":[[-3,2],[3,1]]}"#);
+
+    let bad = [
+        r#"{"offset":-4,"capacity":8,"entries":[[-5,2]]}"#,
+        r#"{"offset":-4,"capacity":8,"entries":[[4,2]]}"#,
+        r#"{"offset":9223372036854775801,"capacity":8,"entries":[]}"#,
+        r#"{"offset":0,"capacity":1152921504606846975,"entries":[]}"#,
+    ];
+    for json in bad.iter() {
+        assert!(serde_json::from_str::<ExtensibleFenwickTree<i32>>(json).is_err());
+    }
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn rbt_serde_format() {
+    let mut t = CumlTree::with_pruning();
+    t.insert("b".to_string(), 2);
+    t.insert("a".to_string(), 1);
+    let json = serde_json::to_string(&t).unwrap();
+    assert_eq!(json, r#"{"pruning":true,"entries":[["a",1],["b",2]]}"#);
+
+    let mut u: CumlTree<String, i32> = serde_json::from_str(&json).unwrap();
+    u.check_invariants();
+    u.insert("a".to_string(), -1);
+    assert_eq!(u.len(), 1);
+
+    let bad = [
+        r#"{"pruning":false,"entries":[["b",1],["a",2]]}"#,
+        r#"{"pruning":false,"entries":[["a",1],["a",2]]}"#,
+        r#"{"pruning":true,"entries":[["a",0]]}"#,
+    ];
+    for json in bad.iter() {
+        assert!(serde_json::from_str::<CumlTree<String, i32>>(json).is_err());
+    }
+}
+
+#[test]
+fn ftf_snapshot_view() {
+    let vals: Vec<i64> = (0..1000).map(|i| (i * 7919) % 13 - 4).collect();
+    let t = FenwickTree::from_slice(&vals);
+    let mut bytes = Vec::new();
+    t.write_snapshot(&mut bytes).unwrap();
+    assert_eq!(bytes.len(), 32 + 8 * 1000);
+
+    let view: FenwickTreeView<i64> = FenwickTreeView::new(&bytes).unwrap();
+    assert_eq!(view.capacity(), 1000);
+    for k in 0..1001 {
+        assert_eq!(view.get_single(k), t.get_single(k));
+        assert_eq!(view.get_cuml(k), t.get_cuml(k));
+        assert_eq!(view.get_range(k / 2..k), t.get_range(k / 2..k));
+    }
+
+    let u = FenwickTree::from_slice(&[3u32, 0, 2, 5, 1]);
+    let mut bytes = Vec::new();
+    u.write_snapshot(&mut bytes).unwrap();
+    let view: FenwickTreeView<u32> = FenwickTreeView::new(&bytes).unwrap();
+    for q in 0..13 {
+        assert_eq!(view.get_quantile(q), u.get_quantile(q));
+    }
+    let copy = view.to_tree();
+    assert_eq!(copy.iter().collect::<Vec<_>>(), u.iter().collect::<Vec<_>>());
+}
+
+#[test]
+fn ftf_snapshot_format() {
+    let t = FenwickTree::from_slice(&[1u16, 2]);
+    let mut bytes = Vec::new();
+    t.write_snapshot(&mut bytes).unwrap();
+    assert_eq!(&bytes[0..8], b"CUMLFWT\0");
+    assert_eq!(&bytes[8..24], &[1, 0, 0, 0, 7, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(&bytes[32..], &[1, 0, 2, 0]);
+
+    let empty: FenwickTree<f64> = FenwickTree::with_capacity(0);
+    let mut bytes = Vec::new();
+    empty.write_snapshot(&mut bytes).unwrap();
+    let view: FenwickTreeView<f64> = FenwickTreeView::new(&bytes).unwrap();
+    assert_eq!(view.get_cuml(3), 0.0);
+    assert_eq!(view.get_quantile(1.0), None);
+}
+
+#[test]
+fn ftf_snapshot_errors() {
+    let t = FenwickTree::from_slice(&[1u32, 2, 3]);
+    let mut bytes = Vec::new();
+    t.write_snapshot(&mut bytes).unwrap();
+
+    let load = |b: &[u8]| FenwickTreeView::<u32>::new(b).map(|_| ());
+    assert_eq!(load(&bytes[..20]), Err(SnapshotError::BadLength));
+    assert_eq!(load(&bytes[..bytes.len() - 1]), Err(SnapshotError::BadLength));
+    assert_eq!(
+        FenwickTreeView::<i32>::new(&bytes).map(|_| ()),
+        Err(SnapshotError::WrongValueType { expected: 3, found: 8 })
+    );
+
+    let mut bad = bytes.clone();
+    bad[0] = b'X';
+    assert_eq!(load(&bad), Err(SnapshotError::BadMagic));
+
+    let mut bad = bytes.clone();
+    bad[8] = 2;
+    assert_eq!(load(&bad), Err(SnapshotError::UnsupportedVersion(2)));
+
+    let mut bad = bytes.clone();
+    bad[36] ^= 1;
+    assert_eq!(load(&bad), Err(SnapshotError::BadChecksum));
+    assert!(FenwickTreeView::<u32>::new_unverified(&bad).is_ok());
+}
+
+fn hash_of<T: std::hash::Hash>(t: &T) -> u64 {
+    use std::hash::Hasher;
+    let mut h = std::collections::hash_map::DefaultHasher::new();
+    t.hash(&mut h);
+    h.finish()
+}
+
+macro_rules! test_std_traits {
+    ($testn:ident, $t:ty, $init:expr, $other:expr) => {
+        #[test]
+        fn $testn() {
+            let mut t: $t = $init;
+            t.insert(1, 2);
+            t.insert(4, 5);
+
+            let mut u = t.clone();
+            assert_eq!(t, u);
+            u.insert(4, 1);
+            assert_ne!(t, u);
+            assert_eq!(t.get_single(4), 5);
+            u.insert(4, -1);
+            assert_eq!(t, u);
+
+            let mut w: $t = $other;
+            w.insert(4, 5);
+            w.insert(2, 0);
+            w.insert(1, 2);
+            assert_eq!(t, w);
+            assert_eq!(hash_of(&t), hash_of(&w));
+            assert_eq!(format!("{:?}", w), "{1: 2, 4: 5}");
+
+            let d: $t = Default::default();
+            assert_eq!(format!("{:?}", d), "{}");
+            assert_ne!(d, t);
+            w.set(1, 0);
+            w.set(4, 0);
+            assert_eq!(d, w);
+            assert_eq!(hash_of(&d), hash_of(&w));
+        }
+    };
+}
+
+test_std_traits!(ftf_std_traits, FenwickTree<i32>,
+                 FenwickTree::with_capacity(6), FenwickTree::with_capacity(10));
+test_std_traits!(fte_std_traits, ExtensibleFenwickTree<i32>,
+                 ExtensibleFenwickTree::new(), ExtensibleFenwickTree::with_extent(-5, 20));
+test_std_traits!(rbt_std_traits, CumlTree<i32, i32>,
+                 CumlTree::new(), CumlTree::with_pruning());
+test_std_traits!(cft_std_traits, CompressedFenwickTree<i32, i32>,
+                 CompressedFenwickTree::new(vec![1, 4]), CompressedFenwickTree::new(-3..9));
+test_std_traits!(sft_std_traits, SparseFenwickTree<i32>,
+                 SparseFenwickTree::new(), SparseFenwickTree::new());
+
+#[test]
+fn rbt_clone_deep() {
+    let mut t: CumlTree<String, i32> = (0..100).map(|i| (i.to_string(), i)).collect();
+    let u = t.clone();
+    for i in 0..50 {
+        t.remove(i.to_string());
+    }
+    t.check_invariants();
+    u.check_invariants();
+    assert_eq!(u.len(), 100);
+    assert_eq!(u.get_cuml("99".to_string()), 4950);
+}
+
+#[test]
+fn rbt_send_sync() {
+    use std::sync::Arc;
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<CumlTree<String, Vec<i64>>>();
+    assert_send_sync::<CumlTreeIter<'static, String, i64>>();
+
+    let t = thread::spawn(|| {
+        let mut t = CumlTree::new();
+        for i in 0..1000i64 {
+            t.insert((i * 7) % 1000, i);
+        }
+        t
+    }).join().unwrap();
+
+    let t = Arc::new(t);
+    let handles: Vec<_> = (0..8i64)
+        .map(|n| {
+            let t = Arc::clone(&t);
+            thread::spawn(move || {
+                for i in (n..1000).step_by(8) {
+                    let k = (i * 7) % 1000;
+                    assert_eq!(t.get_single(k), i);
+                    assert_eq!(t.get_quantile(t.get_cuml(k)), Some(k));
+                }
+                t.iter().map(|(_, v)| v).sum::<i64>()
+            })
+        })
+        .collect();
+    for h in handles {
+        assert_eq!(h.join().unwrap(), 499500);
+    }
+}
+
+#[test]
+fn ftc_concurrent_stress() {
+    use std::hint::black_box;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    const CAP: usize = 1000;
+    const THREADS: u64 = 8;
+    const PER_THREAD: u64 = 20000;
+    fn keys(t: u64) -> impl Iterator<Item = usize> {
+        let mut x = t.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+        (0..PER_THREAD).map(move |_| {
+            x = x.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((x >> 33) % CAP as u64) as usize
+        })
+    }
+
+    let ft: Arc<ConcurrentFenwickTree<u64>> = Arc::new(ConcurrentFenwickTree::with_capacity(CAP));
+    let done = Arc::new(AtomicBool::new(false));
+    let reader = {
+        let (ft, done) = (Arc::clone(&ft), Arc::clone(&done));
+        thread::spawn(move || {
+            // Every insertion adds 3, so with each counted in full or not at
+            // all, cumulative values are multiples of 3 which never decrease.
+            let mut last = vec![0; CAP];
+            while !done.load(Ordering::Relaxed) {
+                for (k, l) in last.iter_mut().enumerate().step_by(37) {
+                    let c = ft.get_cuml(k);
+                    assert_eq!(c % 3, 0);
+                    assert!(c >= *l);
+                    *l = c;
+                }
+            }
+        })
+    };
+    // These queries may count part of an insertion, and so see values which
+    // underflow, but must wrap around rather than panic.
+    let overlapping: Vec<_> = (0..3)
+        .map(|r| {
+            let (ft, done) = (Arc::clone(&ft), Arc::clone(&done));
+            thread::spawn(move || {
+                while !done.load(Ordering::Relaxed) {
+                    for k in (r..CAP).step_by(7) {
+                        black_box(ft.get_single(k));
+                        black_box(ft.get_range(k / 2..k));
+                        black_box(ft.get_quantile(3 * k as u64));
+                    }
+                }
+            })
+        })
+        .collect();
+    let writers: Vec<_> = (0..THREADS)
+        .map(|t| {
+            let ft = Arc::clone(&ft);
+            thread::spawn(move || {
+                for k in keys(t) {
+                    ft.insert(k, 3);
+                }
+            })
+        })
+        .collect();
+    for w in writers {
+        w.join().unwrap();
+    }
+    done.store(true, Ordering::Relaxed);
+    reader.join().unwrap();
+    for r in overlapping {
+        r.join().unwrap();
+    }
+
+    let mut expected = FenwickTree::with_capacity(CAP);
+    for t in 0..THREADS {
+        for k in keys(t) {
+            expected.insert(k, 3);
+        }
+    }
+    assert_eq!(ft.to_tree(), expected);
+    assert_eq!(ft.get_cuml(CAP), 3 * THREADS * PER_THREAD);
+    for k in 0..CAP {
+        assert_eq!(ft.get_single(k), expected.get_single(k));
+    }
+    for q in (0..3 * THREADS * PER_THREAD).step_by(997) {
+        assert_eq!(ft.get_quantile(q), expected.get_quantile(q));
+    }
+}
+
+#[test]
+fn ftc_set_remove() {
+    let mut t: ConcurrentFenwickTree<u32> = FenwickTree::from_slice(&[1, 2, 3, 0, 5]).into();
+    t.set(1, 7);
+    t.set(2, 1);
+    assert_eq!(t.get_cuml(4), 14);
+    assert_eq!(t.remove(4), 5);
+    assert_eq!(t.remove(9), 0);
+    assert_eq!(t.get_range(1..), 8);
+    assert_eq!(t.get_quantile(9), Some(2));
+}
+
+#[test]
+fn ftf_merge() {
+    let vals: Vec<i32> = (0..40).map(|i| (i * 17) % 11 - 5).collect();
+    for c in 0..20 {
+        for d in c..20 {
+            let mut a: FenwickTree<i32> = FenwickTree::from_slice(&vals[20..20 + d]);
+            a.merge(&FenwickTree::from_slice(&vals[..c]));
+            let expected: Vec<i32> = (0..d)
+                .map(|i| vals[20 + i] + if i < c { vals[i] } else { 0 })
+                .collect();
+            assert_eq!(a, FenwickTree::from_slice(&expected));
+            for k in 0..d {
+                assert_eq!(a.get_cuml(k), expected[..=k].iter().sum::<i32>());
+            }
+        }
+    }
+}
+
+#[test]
+#[should_panic]
+fn ftf_merge_larger() {
+    let mut a: FenwickTree<i32> = FenwickTree::with_capacity(4);
+    a.merge(&FenwickTree::with_capacity(5));
+}
+
+#[test]
+fn fte_merge() {
+    let entries = [(-30, 2), (-3, 1), (0, 5), (4, 7), (19, 3), (100, 1)];
+    for split in 0..entries.len() {
+        for skew in &[-50, 0, 2, 60] {
+            let mut a: ExtensibleFenwickTree<i32> = ExtensibleFenwickTree::new();
+            let mut b = ExtensibleFenwickTree::new();
+            let mut expected = CumlTree::new();
+            // Give `b` an extent which starts somewhere other than its keys.
+            b.ensure_contains(*skew);
+            for (i, &(k, v)) in entries.iter().enumerate() {
+                if i < split {
+                    a.insert(k, v);
+                } else {
+                    b.insert(k, v);
+                }
+                expected.insert(k, v);
+            }
+            b.insert(4, 1);
+            expected.insert(4, 1);
+            a.merge(&b);
+            for k in -60..110 {
+                assert_eq!(a.get_single(k), expected.get_single(k));
+                assert_eq!(a.get_cuml(k), expected.get_cuml(k));
+            }
+        }
+    }
+}
+
+#[test]
+fn fts_sharded_threads() {
+    use std::sync::Arc;
+    use std::thread;
+
+    let ft: Arc<ShardedFenwickTree<i64>> = Arc::new(ShardedFenwickTree::new(500, 3));
+    let writers: Vec<_> = (0..8)
+        .map(|t| {
+            let ft = Arc::clone(&ft);
+            thread::spawn(move || {
+                for i in 0..5000 {
+                    ft.insert((i * 31 + t) % 500, i as i64 - 2000);
+                }
+            })
+        })
+        .collect();
+    for w in writers {
+        w.join().unwrap();
+    }
+
+    let mut expected = FenwickTree::with_capacity(500);
+    for t in 0..8 {
+        for i in 0..5000 {
+            expected.insert((i * 31 + t) % 500, i as i64 - 2000);
+        }
+    }
+    assert_eq!(ft.merged(), expected);
+    for k in 0..500 {
+        assert_eq!(ft.get_single(k), expected.get_single(k));
+        assert_eq!(ft.get_cuml(k), expected.get_cuml(k));
+        assert_eq!(ft.get_range(k / 2..k), expected.get_range(k / 2..k));
+    }
+}
+
+#[test]
+fn fts_set_remove() {
+    let mut t: ShardedFenwickTree<u32> = ShardedFenwickTree::new(5, 2);
+    for (k, &v) in [1, 2, 3, 0, 5].iter().enumerate() {
+        t.insert(k, v);
+    }
+    t.set(1, 7);
+    t.set(2, 1);
+    assert_eq!(t.get_cuml(4), 14);
+    assert_eq!(t.remove(4), 5);
+    assert_eq!(t.remove(9), 0);
+    assert_eq!(t.get_range(1..), 8);
+    assert_eq!(t.get_quantile(9), Some(2));
+    let u: ShardedFenwickTree<u32> = t.merged().into();
+    assert_eq!(u.shards(), 1);
+    assert_eq!(u.get_cuml(4), 9);
+}
+
+#[test]
+fn ftf_subtract() {
+    let vals: Vec<i32> = (0..40).map(|i| (i * 17) % 11 - 5).collect();
+    for c in 0..20 {
+        for d in c..20 {
+            let mut a: FenwickTree<i32> = FenwickTree::from_slice(&vals[20..20 + d]);
+            let b = FenwickTree::from_slice(&vals[..c]);
+            a.subtract(&b);
+            let expected: Vec<i32> = (0..d)
+                .map(|i| vals[20 + i] - if i < c { vals[i] } else { 0 })
+                .collect();
+            assert_eq!(a, FenwickTree::from_slice(&expected));
+            a.merge_from(&b);
+            assert_eq!(a, FenwickTree::from_slice(&vals[20..20 + d]));
+        }
+    }
+}
+
+#[test]
+fn fte_subtract() {
+    let mut hour: ExtensibleFenwickTree<i64> = ExtensibleFenwickTree::new();
+    let mut minutes = Vec::new();
+    for m in 0..60 {
+        let mut minute = ExtensibleFenwickTree::new();
+        for i in 0..10 {
+            minute.insert((m * 7 + i * 13) % 90 - 45, 1);
+        }
+        hour.merge_from(&minute);
+        minutes.push(minute);
+    }
+    assert_eq!(hour.get_cuml(100), 600);
+    let mut expected = CumlTree::new();
+    for m in 0..60 {
+        for i in 0..10 {
+            expected.insert((m * 7 + i * 13) % 90 - 45, 1);
+        }
+    }
+    for k in -50..50 {
+        assert_eq!(hour.get_single(k), expected.get_single(k));
+    }
+    for minute in &minutes {
+        hour.subtract(minute);
+    }
+    assert_eq!(hour, ExtensibleFenwickTree::new());
+}
+
+#[test]
+fn rbt_merge_subtract() {
+    // Sizes either side of the switch from inserting to rebuilding.
+    for &(n, m) in &[(0, 0), (0, 5), (5, 0), (100, 3), (100, 60), (3, 100)] {
+        for &pruning in &[false, true] {
+            let mut a: CumlTree<i32, i32> = if pruning { CumlTree::with_pruning() } else { CumlTree::new() };
+            let mut b = CumlTree::new();
+            let mut expected = vec![0; 400];
+            for i in 0..n {
+                let k = (i * 7) % 300;
+                a.insert(k, i % 5 - 2);
+                expected[k as usize] += i % 5 - 2;
+            }
+            for i in 0..m {
+                let k = (i * 11) % 400;
+                b.insert(k, i % 3);
+            }
+            let orig = a.clone();
+            a.merge_from(&b);
+            a.check_invariants();
+            for (k, v) in b.iter() {
+                expected[*k as usize] += v;
+            }
+            for k in 0..400 {
+                assert_eq!(a.get_single(k as i32), expected[k]);
+                assert_eq!(a.get_cuml(k as i32), expected[..=k].iter().sum::<i32>());
+            }
+            if pruning {
+                assert!(a.iter().all(|e| e.1 != 0));
+            }
+            a.subtract(&b);
+            a.check_invariants();
+            assert_eq!(a, orig);
+            if pruning {
+                assert!(a.iter().all(|e| e.1 != 0));
+            }
+        }
+    }
+}
+
+#[test]
+fn ftc_fts_merge_subtract() {
+    let a: FenwickTree<u64> = FenwickTree::from_slice(&[4, 1, 0, 3, 2]);
+    let b: FenwickTree<u64> = FenwickTree::from_slice(&[1, 1, 0, 2]);
+    let mut expected = a.clone();
+    expected.merge_from(&b);
+
+    let mut ca: ConcurrentFenwickTree<u64> = a.clone().into();
+    let cb: ConcurrentFenwickTree<u64> = b.clone().into();
+    ca.merge_from(&cb);
+    assert_eq!(ca.to_tree(), expected);
+    ca.subtract(&cb);
+    assert_eq!(ca.to_tree(), a);
+
+    let mut sa: ShardedFenwickTree<u64> = ShardedFenwickTree::new(5, 3);
+    for (k, v) in a.iter() {
+        sa.insert(k, v);
+    }
+    let sb: ShardedFenwickTree<u64> = b.clone().into();
+    sa.merge_from(&sb);
+    assert_eq!(sa.merged(), expected);
+    sa.subtract(&sb);
+    assert_eq!(sa.merged(), a);
+}
+
+macro_rules! test_range_update {
+    ($name:ident, $tree:ty) => {
+        #[test]
+        fn $name() {
+            const CAP: usize = 50;
+            let mut t: $tree = <$tree>::with_capacity(CAP);
+            let mut naive = vec![0i64; CAP];
+            for i in 0..200 {
+                let lo = (i * 37) % CAP;
+                let hi = lo + (i * 11) % (CAP - lo);
+                t.insert_range(lo, hi, (i % 4) as i64);
+                for v in &mut naive[lo..=hi] {
+                    *v += (i % 4) as i64;
+                }
+            }
+            t.insert(7, 5);
+            naive[7] += 5;
+            t.set(20, 3);
+            naive[20] = 3;
+            assert_eq!(t.remove(31), naive[31]);
+            naive[31] = 0;
+            assert_eq!(t.remove(CAP), 0);
+
+            for k in 0..CAP {
+                assert_eq!(t.get_single(k), naive[k]);
+                assert_eq!(t.get_cuml(k), naive[..=k].iter().sum::<i64>());
+                for j in k..CAP {
+                    assert_eq!(t.get_range(k..j), naive[k..j].iter().sum::<i64>());
+                }
+            }
+            assert_eq!(t.get_single(CAP), 0);
+            assert_eq!(t.get_cuml(CAP + 5), naive.iter().sum::<i64>());
+            assert_eq!(t.get_range(..), naive.iter().sum::<i64>());
+            assert_eq!(t.iter().map(|e| e.1).collect::<Vec<_>>(), naive);
+
+            let total: i64 = naive.iter().sum();
+            for q in 0..total + 2 {
+                let expected = (0..CAP).find(|&k| naive[..=k].iter().sum::<i64>() >= q);
+                assert_eq!(t.get_quantile(q), expected);
+            }
+        }
+    };
+}
+
+test_range_update!(ftru_range_update, RangeUpdateFenwickTree<i64>);
+test_range_update!(ftrr_range_update, RangeUpdateRangeQueryFenwickTree<i64>);
+
+#[test]
+fn ftrr_empty() {
+    let t: RangeUpdateRangeQueryFenwickTree<f64> = Default::default();
+    assert_eq!(t.get_cuml(3), 0.0);
+    assert_eq!(t.get_range(..), 0.0);
+    assert_eq!(t.get_quantile(0.0), None);
+    let t: RangeUpdateFenwickTree<f64> = Default::default();
+    assert_eq!(t.get_cuml(3), 0.0);
+    assert_eq!(t.get_quantile(0.0), None);
+}
+
+#[test]
+#[should_panic]
+fn ftrr_range_beyond_capacity() {
+    let mut t: RangeUpdateRangeQueryFenwickTree<i32> = RangeUpdateRangeQueryFenwickTree::with_capacity(10);
+    t.insert_range(5, 10, 1);
+}
+
+#[test]
+fn ftrr_wrapping_debug() {
+    use std::num::Wrapping;
+    let mut t: RangeUpdateRangeQueryFenwickTree<Wrapping<u32>> =
+        RangeUpdateRangeQueryFenwickTree::with_capacity(6);
+    t.insert_range(1, 2, Wrapping(4));
+    t.insert_range(2, 5, Wrapping(1));
+    assert_eq!(t.get_range(2..5), Wrapping(7));
+    assert_eq!(format!("{:?}", t), "{1: 4, 2: 5, 3: 1, 4: 1, 5: 1}");
+}
+
+#[test]
+fn ft2d_rectangles() {
+    const W: usize = 13;
+    const H: usize = 9;
+    let mut t: FenwickTree2D<i32> = FenwickTree2D::with_capacity(W, H);
+    let mut naive = [[0i32; H]; W];
+    for i in 0..150 {
+        let (x, y) = ((i * 7) % W, (i * 5) % H);
+        t.insert((x, y), (i % 7) as i32 - 2);
+        naive[x][y] += (i % 7) as i32 - 2;
+    }
+    t.set((4, 4), 10);
+    naive[4][4] = 10;
+    assert_eq!(t.remove((6, 2)), naive[6][2]);
+    naive[6][2] = 0;
+    assert_eq!(t.remove((W, 0)), 0);
+
+    let rect = |x0: usize, x1: usize, y0: usize, y1: usize| -> i32 {
+        (x0..x1).flat_map(|x| (y0..y1).map(move |y| (x, y))).map(|(x, y)| naive[x][y]).sum()
+    };
+    for (x, col) in naive.iter().enumerate() {
+        for (y, &v) in col.iter().enumerate() {
+            assert_eq!(t.get_single((x, y)), v);
+            assert_eq!(t.get_cuml((x, y)), rect(0, x + 1, 0, y + 1));
+        }
+    }
+    assert_eq!(t.get_cuml((W + 3, H + 3)), rect(0, W, 0, H));
+    for x0 in 0..W {
+        for x1 in x0..=W {
+            for y0 in (0..H).step_by(2) {
+                for y1 in y0..=H {
+                    assert_eq!(t.get_range(x0..x1, y0..y1), rect(x0, x1, y0, y1));
+                }
+            }
+        }
+    }
+    assert_eq!(t.get_range(.., 3..=3), rect(0, W, 3, 4));
+    assert_eq!(t.capacity(), (W, H));
+}
+
+#[test]
+fn ftnd_matches_naive() {
+    let dims = [5, 4, 6];
+    let mut t: FenwickTreeND<i64, 3> = FenwickTreeND::with_capacity(dims);
+    let mut naive = vec![0i64; 5 * 4 * 6];
+    let at = |k: [usize; 3]| (k[0] * 4 + k[1]) * 6 + k[2];
+    for i in 0..300 {
+        let k = [(i * 3) % 5, (i * 7) % 4, (i * 11) % 6];
+        t.insert(k, i as i64 % 9 - 4);
+        naive[at(k)] += i as i64 % 9 - 4;
+    }
+    let boxed = |lo: [usize; 3], hi: [usize; 3]| -> i64 {
+        let mut sum = 0;
+        for a in lo[0]..hi[0] {
+            for b in lo[1]..hi[1] {
+                for c in lo[2]..hi[2] {
+                    sum += naive[at([a, b, c])];
+                }
+            }
+        }
+        sum
+    };
+    for a in 0..5 {
+        for b in 0..4 {
+            for c in 0..6 {
+                assert_eq!(t.get_single([a, b, c]), naive[at([a, b, c])]);
+                assert_eq!(t.get_cuml([a, b, c]), boxed([0; 3], [a + 1, b + 1, c + 1]));
+                assert_eq!(t.get_range([a..5, 1..b + 1, c / 2..c]), boxed([a, 1, c / 2], [5, b + 1, c]));
+            }
+        }
+    }
+}
+
+#[test]
+fn ftnd_one_dimension() {
+    let vals = [3u32, 0, 1, 4, 1, 5, 9, 2, 6];
+    let ft = FenwickTree::from_slice(&vals);
+    let mut t: FenwickTreeND<u32, 1> = FenwickTreeND::with_capacity([vals.len()]);
+    for (k, &v) in vals.iter().enumerate() {
+        t.insert([k], v);
+    }
+    for k in 0..vals.len() + 2 {
+        assert_eq!(t.get_cuml([k]), ft.get_cuml(k));
+        assert_eq!(t.get_single([k]), ft.get_single(k));
+        assert_eq!(t.get_range([k..]), ft.get_range(k..));
+    }
+}
+
+#[test]
+fn ftnd_empty() {
+    let t: FenwickTreeND<f64, 2> = Default::default();
+    assert_eq!(t.get_cuml([3, 3]), 0.0);
+    assert_eq!(t.get_range([.., ..]), 0.0);
+    let t: FenwickTree2D<f64> = FenwickTree2D::with_capacity(4, 0);
+    assert_eq!(t.get_cuml((3, 3)), 0.0);
+    assert_eq!(t.get_single((1, 0)), 0.0);
+}
+
+#[test]
+#[should_panic]
+fn ft2d_insert_beyond_capacity() {
+    let mut t: FenwickTree2D<i32> = FenwickTree2D::with_capacity(4, 4);
+    t.insert((1, 4), 1);
+}
+
+macro_rules! test_segment {
+    ($name:ident, $op:ty, $fold:expr, $add:expr, $id:expr, $monotone:expr) => {
+        #[test]
+        fn $name() {
+            const CAP: usize = 37;
+            let fold = $fold;
+            let mut st: SegmentTree<i64, $op> = SegmentTree::from_slice(&[0; CAP]);
+            let mut naive = vec![0; CAP];
+            let mut x: u64 = 12345;
+            for step in 0..400 {
+                x = x.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                let a = (x >> 33) as usize % CAP;
+                let b = a + (x >> 45) as usize % (CAP - a);
+                let v = (x >> 20) as i64 % 100 - 50;
+                match step % 4 {
+                    0 => {
+                        st.assign_range(a..=b, v);
+                        naive[a..=b].iter_mut().for_each(|e| *e = v);
+                    },
+                    1 => {
+                        st.add_range(a..b, v);
+                        naive[a..b].iter_mut().for_each(|e| *e += v);
+                    },
+                    2 => {
+                        st.insert(a, v);
+                        naive[a] = fold(naive[a], v);
+                    },
+                    _ => {
+                        st.set(b, v);
+                        naive[b] = v;
+                    },
+                }
+                let (lo, hi) = (a / 2, (b + CAP) / 2);
+                assert_eq!(st.get_range(lo..hi), naive[lo..hi].iter().cloned().fold($id, fold));
+            }
+            assert_eq!(st.remove(5), naive[5]);
+            naive[5] = $id;
+            assert_eq!(st.remove(CAP), $id);
+            for lo in 0..=CAP {
+                assert_eq!(st.get_single(lo), naive.get(lo).cloned().unwrap_or($id));
+                assert_eq!(st.get_cuml(lo), naive[..=lo.min(CAP - 1)].iter().cloned().fold($id, fold));
+                for hi in lo..=CAP {
+                    assert_eq!(st.get_range(lo..hi), naive[lo..hi].iter().cloned().fold($id, fold));
+                }
+                // The first key at which the running total passes a bound.
+                for &bound in &[-100, 0, 40, 200] {
+                    let expected = (lo..CAP)
+                        .find(|&k| naive[lo..=k].iter().cloned().fold($id, fold) > bound)
+                        .unwrap_or(CAP);
+                    let pred = |v: &i64| *v <= bound;
+                    if $monotone && pred(&$id) {
+                        assert_eq!(st.max_right(lo, pred), expected);
+                    }
+                }
+            }
+
+            // Starting from the identity at every key, with removals between
+            // additions to ranges.
+            let add = $add;
+            let mut st: SegmentTree<i64, $op> = SegmentTree::with_capacity(CAP);
+            let mut naive = vec![$id; CAP];
+            for step in 0..400 {
+                x = x.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                let a = (x >> 33) as usize % CAP;
+                let b = a + (x >> 45) as usize % (CAP - a);
+                let v = (x >> 20) as i64 % 100 - 50;
+                match step % 4 {
+                    0 => {
+                        st.add_range(a..=b, v);
+                        naive[a..=b].iter_mut().for_each(|e| *e = add(*e, v));
+                    },
+                    1 => {
+                        st.insert(a, v);
+                        naive[a] = fold(naive[a], v);
+                    },
+                    2 => {
+                        assert_eq!(st.remove(b), naive[b]);
+                        naive[b] = $id;
+                    },
+                    _ => {
+                        st.add_range(.., v);
+                        naive.iter_mut().for_each(|e| *e = add(*e, v));
+                    },
+                }
+                assert_eq!(st.get_range(..), naive.iter().cloned().fold($id, fold));
+                assert_eq!(st.get_range(a..), naive[a..].iter().cloned().fold($id, fold));
+                assert_eq!(st.get_single(b), naive[b]);
+            }
+        }
+    };
+}
+
+// Only a running maximum is sure to be monotonic, as `max_right` needs.
+// Adding to a range leaves the identity of `Min` and `Max` unchanged.
+test_segment!(st_sum, Sum, |a: i64, b: i64| a + b, |e: i64, v: i64| e + v, 0, false);
+test_segment!(st_min, Min, |a: i64, b: i64| a.min(b),
+              |e: i64, v: i64| if e == i64::MAX { e } else { e + v }, i64::MAX, false);
+test_segment!(st_max, Max, |a: i64, b: i64| a.max(b),
+              |e: i64, v: i64| if e == i64::MIN { e } else { e + v }, i64::MIN, true);
+
+#[test]
+fn st_add_to_identity() {
+    let mut st: SegmentTree<i32, Min> = SegmentTree::with_capacity(4);
+    st.add_range(.., 1);
+    assert_eq!(st.get_range(..), i32::MAX);
+    st.insert(2, 3);
+    st.add_range(1.., 1);
+    assert_eq!(st.get_range(..), 4);
+
+    let mut st: SegmentTree<i64, Max> = SegmentTree::with_capacity(3);
+    st.insert(1, 5);
+    st.add_range(.., -1);
+    assert_eq!(st.get_range(..), 4);
+    assert_eq!(st.get_single(0), i64::MIN);
+    st.remove(1);
+    st.add_range(.., -1);
+    assert_eq!(st.get_range(..), i64::MIN);
+}
+
+#[test]
+fn st_gcd() {
+    let mut st: SegmentTree<u32, Gcd> = SegmentTree::from_slice(&[12, 18, 30, 7, 49, 14]);
+    assert_eq!(st.get_range(..3), 6);
+    assert_eq!(st.get_range(3..), 7);
+    assert_eq!(st.get_range(..), 1);
+    st.assign_range(1..4, 8);
+    assert_eq!(st.get_range(..5), 1);
+    assert_eq!(st.get_range(..4), 4);
+    assert_eq!(st.max_right(0, |&g| g != 1), 4);
+    let signed: SegmentTree<i32, Gcd> = SegmentTree::from_slice(&[-12, 18]);
+    assert_eq!(signed.get_range(..), 6);
+}
+
+#[test]
+fn st_quantile_and_edges() {
+    let mut st: SegmentTree<u32> = SegmentTree::from_slice(&[1, 0, 4, 2, 0, 3]);
+    assert_eq!(st.get_quantile(0), Some(0));
+    assert_eq!(st.get_quantile(5), Some(2));
+    assert_eq!(st.get_quantile(6), Some(3));
+    assert_eq!(st.get_quantile(11), None);
+    st.add_range(4.., 1);
+    assert_eq!(st.get_quantile(11), Some(5));
+    assert_eq!(format!("{:?}", st), "[1, 0, 4, 2, 1, 4]");
+    assert_eq!(st.clone().get_range(..), 12);
+
+    let empty: SegmentTree<f64, Max> = Default::default();
+    assert_eq!(empty.get_range(..), f64::MIN);
+    assert_eq!(empty.get_quantile(1.0), None);
+    assert_eq!(empty.max_right(0, |_| true), 0);
+    let nan: SegmentTree<f64> = SegmentTree::from_slice(&[1.0, 2.0]);
+    assert_eq!(nan.get_quantile(f64::NAN), None);
+}
+
+#[test]
+fn st_sum_max_right_lazy() {
+    const CAP: usize = 29;
+    let mut st: SegmentTree<u64> = SegmentTree::with_capacity(CAP);
+    let mut naive = [0u64; CAP];
+    for i in 0..60 {
+        let (a, b) = ((i * 7) % CAP, (i * 7) % CAP + (i * 3) % 5);
+        let b = b.min(CAP - 1);
+        if i % 3 == 0 {
+            st.assign_range(a..=b, i as u64 % 4);
+            naive[a..=b].iter_mut().for_each(|e| *e = i as u64 % 4);
+        } else {
+            st.add_range(a..=b, i as u64 % 3);
+            naive[a..=b].iter_mut().for_each(|e| *e += i as u64 % 3);
+        }
+        for lo in 0..=CAP {
+            for bound in 0..20 {
+                let expected = (lo..CAP)
+                    .find(|&k| naive[lo..=k].iter().sum::<u64>() > bound)
+                    .unwrap_or(CAP);
+                assert_eq!(st.max_right(lo, |&s| s <= bound), expected);
+            }
+        }
+    }
+}
+
+#[test]
+fn ftf_try_errors() {
+    let mut ft: FenwickTree<i32> = FenwickTree::with_capacity(8);
+    assert_eq!(ft.try_insert(7, 3), Ok(()));
+    assert_eq!(ft.try_insert(8, 3), Err(Error::KeyOutOfRange));
+    assert_eq!(ft.try_set(2, 4), Ok(()));
+    assert_eq!(ft.try_get_cuml(7), Ok(7));
+    assert_eq!(ft.try_get_cuml(8), Err(Error::KeyOutOfRange));
+    assert_eq!(ft.try_get_single(2), Ok(4));
+    assert_eq!(ft.try_get_range(2..8), Ok(7));
+    assert_eq!(ft.try_get_range(3..=7), Ok(3));
+    assert_eq!(ft.try_get_range(5..5), Ok(0));
+    assert_eq!(ft.try_get_range(..=8), Err(Error::KeyOutOfRange));
+    assert_eq!(ft.try_get_range(..usize::MAX), Err(Error::KeyOutOfRange));
+
+    let mut empty: FenwickTree<i32> = FenwickTree::with_capacity(0);
+    assert_eq!(empty.try_insert(0, 1), Err(Error::ZeroCapacity));
+    assert_eq!(empty.try_get_cuml(0), Err(Error::ZeroCapacity));
+    assert_eq!(empty.try_get_range(..), Err(Error::ZeroCapacity));
+    assert_eq!(empty.checked_get_cuml(0), Err(Error::ZeroCapacity));
+}
+
+#[test]
+fn ftf_checked_overflow() {
+    let mut ft: FenwickTree<i8> = FenwickTree::with_capacity(16);
+    for k in 0..16 {
+        assert_eq!(ft.checked_insert(k, 7), Ok(()));
+    }
+    assert_eq!(ft.checked_get_cuml(15), Ok(112));
+    // Node 16 is beyond capacity, so the largest node updated covers 1..=8.
+    assert_eq!(ft.checked_insert(5, 20), Ok(()));
+    assert_eq!(ft.checked_get_cuml(7), Ok(76));
+    assert_eq!(ft.checked_get_cuml(15), Err(Error::Overflow));
+    assert_eq!(ft.checked_get_range(8..), Ok(56));
+    assert_eq!(ft.checked_remove(5), Ok(27));
+    assert_eq!(ft.checked_get_cuml(15), Ok(105));
+    assert_eq!(ft.checked_insert(3, 100), Err(Error::Overflow));
+    assert_eq!(ft.checked_set(3, 86), Err(Error::Overflow));
+    assert_eq!(ft.checked_get_single(3), Ok(7));
+    assert_eq!(ft.checked_get_cuml(15), Ok(105));
+    assert_eq!(ft.checked_set(3, 85), Ok(()));
+    assert_eq!(ft.checked_get_cuml(7), Ok(127));
+    assert_eq!(ft.checked_remove(16), Err(Error::KeyOutOfRange));
+
+    let mut small: FenwickTree<u8> = FenwickTree::with_capacity(4);
+    small.insert(3, 5);
+    assert_eq!(small.checked_set(3, 2), Ok(()));
+    assert_eq!(small.checked_insert(0, 254), Ok(()));
+    assert_eq!(small.checked_get_single(0), Ok(254));
+    assert_eq!(small.checked_get_cuml(3), Err(Error::Overflow));
+    assert_eq!(small.checked_get_range(1..), Ok(2));
+    assert_eq!(small.checked_remove(2), Ok(0));
+}
+
+#[test]
+fn fte_checked() {
+    let mut ft: ExtensibleFenwickTree<i16> = ExtensibleFenwickTree::new();
+    assert_eq!(ft.checked_get_cuml(3), Ok(0));
+    assert_eq!(ft.checked_insert(-5, 30000), Ok(()));
+    assert_eq!(ft.checked_insert(-5, 3000), Err(Error::Overflow));
+    assert_eq!(ft.checked_insert(10, 2000), Ok(()));
+    assert_eq!(ft.checked_get_cuml(-6), Ok(0));
+    assert_eq!(ft.checked_get_cuml(100), Ok(32000));
+    assert_eq!(ft.checked_get_single(10), Ok(2000));
+    assert_eq!(ft.checked_set(10, -2000), Ok(()));
+    assert_eq!(ft.checked_get_range(0..), Ok(-2000));
+    assert_eq!(ft.checked_remove(-5), Ok(30000));
+    assert_eq!(ft.checked_remove(500), Ok(0));
+    assert_eq!(ft.checked_get_cuml(100), Ok(-2000));
+}
+
+#[test]
+fn fte_checked_growth_overflow() {
+    fn extent(t: &ExtensibleFenwickTree<u8>) -> (i64, i64) {
+        (t.iter().next().unwrap().0, t.iter().next_back().unwrap().0)
+    }
+
+    // Growing the extent puts 200 and 200 into one node, which no node of
+    // the old layout held together.
+    let mut ft: ExtensibleFenwickTree<u8> = ExtensibleFenwickTree::with_extent(0, 8);
+    assert_eq!(ft.checked_insert(0, 200), Ok(()));
+    assert_eq!(ft.checked_insert(1, 200), Ok(()));
+    assert_eq!(ft.checked_insert(-1, 1), Ok(()));
+    assert_eq!(ft.checked_insert(-9, 1), Ok(()));
+    let before = ft.clone();
+    assert_eq!(ft.checked_insert(8, 1), Err(Error::Overflow));
+    assert_eq!(ft, before);
+    assert_eq!(extent(&ft), extent(&before));
+    assert_eq!(ft.checked_get_single(1), Ok(200));
+
+    // Growing succeeds, but the insertion itself then overflows.
+    let mut ft: ExtensibleFenwickTree<u8> = ExtensibleFenwickTree::with_extent(0, 4);
+    assert_eq!(ft.checked_insert(1, 250), Ok(()));
+    assert_eq!(ft.checked_insert(7, 10), Err(Error::Overflow));
+    assert_eq!(extent(&ft), (0, 3));
+    assert_eq!(ft.checked_insert(7, 5), Ok(()));
+    assert_eq!(ft.checked_get_cuml(7), Ok(255));
+}
+
+#[test]
+fn ftf_wrapping_policy() {
+    let mut ft: FenwickTree<Wrapping<u8>> = FenwickTree::with_capacity(8);
+    ft.insert(1, Wrapping(200));
+    ft.insert(3, Wrapping(100));
+    ft.insert(6, Wrapping(10));
+    assert_eq!(ft.get_cuml(2), Wrapping(200));
+    assert_eq!(ft.get_cuml(3), Wrapping(44));
+    assert_eq!(ft.get_cuml(7), Wrapping(54));
+    assert_eq!(ft.get_single(3), Wrapping(100));
+    assert_eq!(ft.get_range(2..7), Wrapping(110));
+    assert_eq!(ft.remove(1), Wrapping(200));
+    assert_eq!(ft.get_cuml(7), Wrapping(110));
+    assert_eq!(ft.get_quantile(Wrapping(101)), Some(6));
+
+    let mut eft: ExtensibleFenwickTree<Wrapping<u32>> = ExtensibleFenwickTree::new();
+    eft.insert(-3, Wrapping(u32::MAX));
+    eft.insert(5, Wrapping(2));
+    eft.insert(-10, Wrapping(1));
+    assert_eq!(eft.get_cuml(4), Wrapping(0));
+    assert_eq!(eft.get_cuml(5), Wrapping(2));
+    assert_eq!(eft.get_single(-3), Wrapping(u32::MAX));
+}
+
+#[test]
+fn ftf_saturating_policy() {
+    let mut ft: FenwickTree<Saturating<u8>> = FenwickTree::with_capacity(12);
+    for k in 0..12 {
+        ft.insert(k, Saturating(50));
+    }
+    assert_eq!(ft.get_cuml(4), Saturating(250));
+    assert_eq!(ft.get_cuml(5), Saturating(u8::MAX));
+    assert_eq!(ft.get_cuml(11), Saturating(u8::MAX));
+    assert_eq!(ft.get_range(..5), Saturating(250));
+    // Exact while the cumulative value at the key is below the maximum.
+    assert_eq!(ft.get_single(4), Saturating(50));
+    assert_eq!(ft.get_single(2), Saturating(50));
+    assert_eq!(ft.get_single(7), Saturating(50));
+    // The node covering keys 1 to 8 has saturated.
+    assert_eq!(ft.get_single(8), Saturating(0));
+    assert_eq!(ft.get_quantile(Saturating(200)), Some(3));
+    assert_eq!(ft.get_quantile(Saturating(251)), Some(5));
+    assert_eq!(ft.get_quantile(Saturating(u8::MAX)), Some(5));
+
+    let mut eft: ExtensibleFenwickTree<Saturating<u32>> = ExtensibleFenwickTree::new();
+    eft.insert(0, Saturating(u32::MAX - 1));
+    eft.insert(2, Saturating(1));
+    assert_eq!(eft.get_cuml(2), Saturating(u32::MAX));
+    assert_eq!(eft.get_quantile(Saturating(u32::MAX)), Some(2));
+    eft.insert(2, Saturating(5));
+    assert_eq!(eft.get_cuml(2), Saturating(u32::MAX));
+    assert_eq!(eft.get_cuml(1), Saturating(u32::MAX - 1));
+}
+
+#[test]
+fn ftf_checked_policy() {
+    let mut ft: FenwickTree<Checked<u32>> = FenwickTree::with_capacity(4);
+    ft.insert(0, Checked(u32::MAX - 5));
+    ft.insert(3, Checked(5));
+    assert_eq!(ft.get_cuml(3), Checked(u32::MAX));
+    assert_eq!(ft.get_single(3), Checked(5));
+    assert_eq!(ft.get_quantile(Checked(u32::MAX)), Some(3));
+    assert_eq!(ft.remove(0), Checked(u32::MAX - 5));
+    assert_eq!(ft.get_cuml(3), Checked(5));
+}
+
+#[test]
+#[should_panic]
+fn ftf_checked_policy_overflow() {
+    let mut ft: FenwickTree<Checked<u8>> = FenwickTree::with_capacity(4);
+    ft.insert(1, Checked(255));
+    ft.insert(1, Checked(1));
+}
+
+#[test]
+#[should_panic]
+fn fte_checked_policy_overflow() {
+    let mut ft: ExtensibleFenwickTree<Checked<u8>> = ExtensibleFenwickTree::new();
+    ft.insert(-1, Checked(255));
+    ft.insert(-2, Checked(1));
+    ft.get_cuml(0);
+}
+
+#[test]
+fn key_conversions() {
+    assert_eq!(key_distance(i8::MIN, i8::MAX), Some(255));
+    assert_eq!(key_distance(-3i32, 4), Some(7));
+    assert_eq!(key_distance(i64::MIN, i64::MAX), Some(u64::MAX as usize));
+    assert_eq!(key_distance(i128::MIN, i128::MAX), None);
+    assert_eq!(key_distance(0u128, u128::MAX), None);
+    assert_eq!(key_offset(i8::MIN, 255), Some(i8::MAX));
+    assert_eq!(key_offset(i8::MIN, 256), None);
+    assert_eq!(key_offset(-1i8, 128), Some(127));
+    assert_eq!(key_offset(250u8, 6), None);
+    assert_eq!(key_offset(i128::MAX - 1, 1), Some(i128::MAX));
+    assert_eq!(key_before(i8::MAX, 255), Some(i8::MIN));
+    assert_eq!(key_before(i8::MAX, 256), None);
+    assert_eq!(key_before(3u16, 3), Some(0));
+    assert_eq!(key_before(3u16, 4), None);
+    assert_eq!(key_before(i128::MIN + 5, 5), Some(i128::MIN));
+    assert_eq!(key_position(-5i32, -3), None);
+    assert_eq!(key_position(u128::MAX, 0), Some(usize::MAX));
+    assert!(extent_fits(u8::MIN, 256));
+    assert!(!extent_fits(1u8, 256));
+}
+
+#[test]
+fn ftf_u16_keys() {
+    let mut ports: FenwickTree<u32, u16> = FenwickTree::with_capacity(65536);
+    ports.insert(22, 3);
+    ports.insert(443, 5);
+    ports.insert(u16::MAX, 1);
+    assert_eq!(ports.get_cuml(1023), 8);
+    assert_eq!(ports.get_range(1024..), 1);
+    assert_eq!(ports.get_range(..=u16::MAX), 9);
+    assert_eq!(ports.get_single(u16::MAX), 1);
+    assert_eq!(ports.get_quantile(9), Some(u16::MAX));
+    assert_eq!(ports.remove(443), 5);
+    let entries: Vec<(u16, u32)> = ports.iter().skip_zeros().collect();
+    assert_eq!(entries, vec![(22, 3), (u16::MAX, 1)]);
+    assert_eq!(ports.try_get_range(..), Ok(4));
+
+    let bytes: FenwickTree<u32, u8> = FenwickTree::with_capacity(256);
+    assert_eq!(bytes.get_cuml(u8::MAX), 0);
+}
+
+#[test]
+#[should_panic]
+fn ftf_capacity_beyond_key_type() {
+    let _: FenwickTree<u32, u8> = FenwickTree::with_capacity(257);
+}
+
+#[test]
+fn ftf_signed_keys() {
+    let mut ft: FenwickTree<i32, i32> = FenwickTree::with_capacity(8);
+    ft.insert(0, 1);
+    ft.insert(7, 2);
+    assert_eq!(ft.get_cuml(-1), 0);
+    assert_eq!(ft.get_cuml(i32::MAX), 3);
+    assert_eq!(ft.get_single(-1), 0);
+    assert_eq!(ft.get_range(-5..3), 1);
+    assert_eq!(ft.get_range(-5..-1), 0);
+    assert_eq!(ft.remove(-1), 0);
+    assert_eq!(ft.try_insert(-1, 1), Err(Error::KeyOutOfRange));
+    assert_eq!(ft.try_get_range(-2..3), Err(Error::KeyOutOfRange));
+    assert_eq!(ft.try_get_range(..-1), Err(Error::KeyOutOfRange));
+    assert_eq!(ft.try_get_range(0..8), Ok(3));
+    assert_eq!(ft.checked_get_cuml(-3), Err(Error::KeyOutOfRange));
+    let keys: Vec<i32> = ft.range(-4..).skip_zeros().map(|e| e.0).collect();
+    assert_eq!(keys, vec![0, 7]);
+}
+
+#[test]
+#[should_panic]
+fn ftf_insert_negative_key() {
+    let mut ft: FenwickTree<i32, i64> = FenwickTree::with_capacity(8);
+    ft.insert(-1, 1);
+}
+
+#[test]
+fn fte_i8_keys() {
+    let mut ft: ExtensibleFenwickTree<u32, i8> = ExtensibleFenwickTree::new();
+    ft.insert(-128, 1);
+    ft.insert(127, 2);
+    ft.insert(0, 4);
+    ft.insert(-100, 8);
+    assert_eq!(ft.get_cuml(-1), 9);
+    assert_eq!(ft.get_cuml(i8::MAX), 15);
+    assert_eq!(ft.get_range(-100..=0), 12);
+    assert_eq!(ft.get_range(1..), 2);
+    assert_eq!(ft.get_quantile(15), Some(127));
+    let entries: Vec<(i8, u32)> = ft.iter().skip_zeros().rev().collect();
+    assert_eq!(entries, vec![(127, 2), (0, 4), (-100, 8), (-128, 1)]);
+    assert_eq!(ft.remove(-128), 1);
+    assert_eq!(ft.checked_get_cuml(-1), Ok(8));
+}
+
+#[test]
+fn fte_i32_keys() {
+    let mut deltas: ExtensibleFenwickTree<i64, i32> = ExtensibleFenwickTree::new();
+    for d in -50..50 {
+        deltas.insert(d * 3, d as i64);
+    }
+    assert_eq!(deltas.get_cuml(i32::MIN), 0);
+    assert_eq!(deltas.get_cuml(-1), (-50..0).sum::<i64>());
+    assert_eq!(deltas.get_cuml(i32::MAX), -50);
+    assert_eq!(deltas.get_single(-3), -1);
+    assert_eq!(deltas.get_single(-2), 0);
+    assert_eq!(deltas.get_range(i32::MIN..i32::MAX), -50);
+
+    let mut edge: ExtensibleFenwickTree<i64, i32> = ExtensibleFenwickTree::with_extent(i32::MAX - 3, 4);
+    edge.insert(i32::MAX, 5);
+    edge.insert(i32::MAX - 10, 1);
+    assert_eq!(edge.get_cuml(i32::MAX), 6);
+    assert_eq!(edge.get_cuml(i32::MIN), 0);
+    assert_eq!(edge.get_quantile(6), Some(i32::MAX));
+}
+
+#[test]
+fn fte_u64_keys() {
+    const T0: u64 = 1_700_000_000_000;
+    let mut ft: ExtensibleFenwickTree<u32, u64> = ExtensibleFenwickTree::with_extent(T0, 16);
+    ft.insert(T0 + 3, 2);
+    ft.insert(T0 - 5, 1);
+    ft.insert(T0 + 40, 4);
+    assert_eq!(ft.get_cuml(0), 0);
+    assert_eq!(ft.get_cuml(T0), 1);
+    assert_eq!(ft.get_cuml(u64::MAX), 7);
+    assert_eq!(ft.get_range(T0..=T0 + 40), 6);
+    assert_eq!(ft.get_quantile(3), Some(T0 + 3));
+
+    let mut top: ExtensibleFenwickTree<u32, u64> = ExtensibleFenwickTree::new();
+    top.insert(5, 1);
+    top.insert(0, 2);
+    assert_eq!(top.get_cuml(4), 2);
+    let mut wide: ExtensibleFenwickTree<u32, u128> = ExtensibleFenwickTree::with_extent(u128::MAX - 1, 2);
+    wide.insert(u128::MAX, 3);
+    wide.insert(u128::MAX - 9, 1);
+    assert_eq!(wide.get_cuml(u128::MAX - 1), 1);
+    assert_eq!(wide.get_range(..), 4);
+}
+
+#[test]
+fn cft_matches_cuml_tree() {
+    let universe: Vec<u64> = (0..200u64).map(|i| i * i * 1_000_003 % 999_999_937).collect();
+    let mut ct: CompressedFenwickTree<u64, i64> = CompressedFenwickTree::new(universe.clone());
+    let mut rb: CumlTree<u64, i64> = CumlTree::new();
+    for (i, &k) in universe.iter().enumerate().filter(|e| e.0 % 3 != 1) {
+        ct.insert(k, (i % 7) as i64);
+        rb.insert(k, (i % 7) as i64);
+    }
+    assert_eq!(ct.len(), universe.len());
+    for &k in universe.iter().step_by(5) {
+        assert_eq!(ct.get_cuml(k), rb.get_cuml(k));
+        assert_eq!(ct.get_cuml(k + 1), rb.get_cuml(k + 1));
+        assert_eq!(ct.get_single(k), rb.get_single(k));
+        assert_eq!(ct.get_range(k / 2..k), rb.get_range(k / 2..k));
+        assert_eq!(ct.get_range((Bound::Excluded(k), Bound::Unbounded)),
+                   rb.get_range((Bound::Excluded(k), Bound::Unbounded)));
+    }
+    for q in 0..rb.get_cuml(u64::MAX) + 2 {
+        assert_eq!(ct.get_quantile(q), rb.get_quantile(q));
+    }
+    let a: Vec<(u64, i64)> = ct.iter().skip_zeros().map(|(&k, v)| (k, v)).collect();
+    let b: Vec<(u64, i64)> = rb.iter().filter(|e| e.1 != 0).map(|(&k, v)| (k, v)).collect();
+    assert_eq!(a, b);
+    for (&k, v) in ct.range(1000..).cumulative().rev() {
+        assert_eq!(v, rb.get_range(1000..=k));
+    }
+}
+
+#[test]
+fn cft_string_keys() {
+    let words = ["pear", "apple", "fig", "kiwi", "apple"];
+    let mut ct: CompressedFenwickTree<String, u32> =
+        CompressedFenwickTree::new(words.iter().map(|w| w.to_string()));
+    assert_eq!(ct.keys(), ["apple", "fig", "kiwi", "pear"]);
+    ct.insert("fig".to_string(), 2);
+    ct.insert("pear".to_string(), 3);
+    assert_eq!(ct.get_cuml("grape".to_string()), 2);
+    assert_eq!(ct.get_quantile(3), Some("pear".to_string()));
+    assert_eq!(ct.get_quantile_ref(1).map(|s| s.as_str()), Some("fig"));
+    assert_eq!(ct.get_quantile(6), None);
+    assert_eq!(ct.remove("banana".to_string()), 0);
+    assert_eq!(format!("{:?}", ct), r#"{"fig": 2, "pear": 3}"#);
+}
+
+#[test]
+fn cft_merge_subtract() {
+    let mut a: CompressedFenwickTree<i32, i32> = CompressedFenwickTree::new(vec![-5, 0, 5, 10]);
+    let mut b = a.clone();
+    a.insert(0, 3);
+    b.insert(0, 1);
+    b.insert(10, 4);
+    a.merge_from(&b);
+    assert_eq!(a.get_single(0), 4);
+    assert_eq!(a.get_cuml(10), 8);
+
+    let mut c: CompressedFenwickTree<i32, i32> = CompressedFenwickTree::new(vec![5, 10, 99]);
+    c.insert(10, 2);
+    a.subtract(&c);
+    assert_eq!(a.get_single(10), 2);
+    assert_eq!(a.get_cuml(9), 4);
+
+    // Unsigned values must not pass through a negative delta.
+    let mut d: CompressedFenwickTree<i32, u32> = CompressedFenwickTree::new(vec![1, 2, 3]);
+    d.insert(2, 5);
+    let mut e: CompressedFenwickTree<i32, u32> = CompressedFenwickTree::new(vec![2]);
+    e.insert(2, 3);
+    d.subtract(&e);
+    assert_eq!(d.get_single(2), 2);
+    assert_eq!(d.get_cuml(3), 2);
+    d.merge_from(&e);
+    assert_eq!(d.get_single(2), 5);
+}
+
+#[test]
+#[should_panic]
+fn cft_insert_outside_universe() {
+    let mut ct: CompressedFenwickTree<i32, i32> = CompressedFenwickTree::new(vec![1, 3]);
+    ct.insert(2, 1);
+}
+
+#[test]
+#[should_panic]
+fn cft_from_unsorted_keys() {
+    let _: CompressedFenwickTree<i32, i32> = CompressedFenwickTree::from_sorted_keys(vec![1, 3, 3]);
+}
+
+#[test]
+fn sft_matches_cuml_tree() {
+    let keys: Vec<u64> = (0..500u64).map(|i| i.wrapping_mul(0x9e37_79b9_7f4a_7c15)).collect();
+    let mut sf: SparseFenwickTree<i64> = SparseFenwickTree::new();
+    let mut rb: CumlTree<u64, i64> = CumlTree::new();
+    for (i, &k) in keys.iter().enumerate() {
+        let v = (i % 7) as i64 - 2;
+        sf.insert(k, v);
+        rb.insert(k, v);
+    }
+    for &k in keys.iter().step_by(3) {
+        sf.set(k, 3);
+        rb.set(k, 3);
+    }
+    for &k in keys.iter().skip(1).step_by(11) {
+        assert_eq!(sf.remove(k), rb.remove(k));
+    }
+    for &k in &keys {
+        assert_eq!(sf.get_cuml(k), rb.get_cuml(k));
+        assert_eq!(sf.get_cuml(k.wrapping_sub(1)), rb.get_cuml(k.wrapping_sub(1)));
+        assert_eq!(sf.get_single(k), rb.get_single(k));
+        assert_eq!(sf.get_range(k / 2..k), rb.get_range(k / 2..k));
+        assert_eq!(sf.get_range((Bound::Excluded(k), Bound::Unbounded)),
+                   rb.get_range((Bound::Excluded(k), Bound::Unbounded)));
+    }
+    assert_eq!(sf.get_cuml(u64::MAX), rb.get_cuml(u64::MAX));
+    assert_eq!(sf.get_range(..), rb.get_range(..));
+    let a: Vec<(u64, i64)> = sf.iter().collect();
+    let b: Vec<(u64, i64)> = rb.iter().filter(|e| e.1 != 0).map(|(&k, v)| (k, v)).collect();
+    assert_eq!(a, b);
+    let c: Vec<(u64, i64)> = sf.iter().rev().collect();
+    assert_eq!(c, b.into_iter().rev().collect::<Vec<_>>());
+}
+
+#[test]
+fn sft_quantile_matches_cuml_tree() {
+    let mut sf: SparseFenwickTree<u64> = SparseFenwickTree::new();
+    let mut rb: CumlTree<u64, u64> = CumlTree::new();
+    for i in 0..300u64 {
+        let k = i.wrapping_mul(0xbf58_476d_1ce4_e5b9) >> (i % 5);
+        sf.insert(k, i % 4);
+        rb.insert(k, i % 4);
+    }
+    sf.insert(u64::MAX, 2);
+    rb.insert(u64::MAX, 2);
+    for q in 0..rb.get_cuml(u64::MAX) + 2 {
+        assert_eq!(sf.get_quantile(q), rb.get_quantile(q));
+    }
+}
+
+#[test]
+fn sft_extreme_keys() {
+    let mut sf: SparseFenwickTree<i32> = SparseFenwickTree::new();
+    sf.insert(u64::MAX, 5);
+    sf.insert(0, 1);
+    sf.insert(1 << 63, 2);
+    assert_eq!(sf.get_single(u64::MAX), 5);
+    assert_eq!(sf.get_cuml(u64::MAX - 1), 3);
+    assert_eq!(sf.get_range(u64::MAX..), 5);
+    assert_eq!(sf.get_range((Bound::Excluded(u64::MAX), Bound::Unbounded)), 0);
+    assert_eq!(sf.get_range(..0), 0);
+    assert_eq!(sf.get_quantile(8), Some(u64::MAX));
+    assert_eq!(sf.get_quantile(9), None);
+    assert_eq!(sf.get_quantile(0), Some(0));
+    // Each of these keys lies on no other node's update path.
+    assert_eq!(sf.node_count(), 3);
+    sf.insert(5, 1);
+    assert_eq!(sf.node_count(), 65);
+}
+
+#[test]
+fn sft_merge_subtract() {
+    let mut a: SparseFenwickTree<i32> = SparseFenwickTree::new();
+    let mut b: SparseFenwickTree<i32> = SparseFenwickTree::new();
+    a.insert(0, 3);
+    a.insert(1 << 40, 1);
+    b.insert(0, 1);
+    b.insert(u64::MAX, 4);
+    a.merge_from(&b);
+    assert_eq!(a.get_single(0), 4);
+    assert_eq!(a.get_cuml(u64::MAX), 9);
+    a.subtract(&b);
+    a.subtract(&b);
+    assert_eq!(a.get_single(u64::MAX), -4);
+    assert_eq!(format!("{:?}", a), "{0: 2, 1099511627776: 1, 18446744073709551615: -4}");
+}